@@ -7,8 +7,11 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! It also turns the typo list in `autocorrect.txt` into the trie used by
+//! `src/autocorrect.rs`.
 
-use std::{env, fs::File, io::Write, path::PathBuf};
+use std::{collections::BTreeMap, env, fs, fs::File, io::Write, path::Path, path::PathBuf};
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -22,4 +25,101 @@ fn main() {
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
+
+    generate_autocorrect(out);
+    println!("cargo:rerun-if-changed=autocorrect.txt");
+}
+
+/// A node of the autocorrect trie, keyed by the characters of the typos in reverse.
+#[derive(Default)]
+struct TrieNode {
+    children: BTreeMap<u8, TrieNode>,
+
+    /// The index of the correction, if a typo ends here.
+    correction: Option<usize>,
+}
+
+/// Marks a leaf node in the trie, followed by the index of its correction.
+const LEAF: u8 = 0x80;
+
+/// Reads `autocorrect.txt`, where each line is a `typo -> correction` pair, and writes
+/// `autocorrect.rs` with the typos as a trie of bytes and the corrections as macros.
+///
+/// A `:` at the start or end of a typo matches a word boundary, so `:teh:` only matches the
+/// whole word. Typos are stored in reverse, so the firmware can walk the trie backwards
+/// from the last character typed. Each node is a list of `character, offset (u16, LE)`
+/// entries ending with 0, or `LEAF` followed by the index of the correction (u16, LE).
+fn generate_autocorrect(out: &Path) {
+    let list = fs::read_to_string("autocorrect.txt").unwrap();
+
+    let mut root = TrieNode::default();
+    let mut corrections = Vec::new();
+    for (line_number, line) in list.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((typo, correction)) = line.split_once("->") else {
+            panic!("autocorrect.txt:{}: expected `typo -> correction`", line_number + 1);
+        };
+
+        let (typo, correction) = (typo.trim().to_ascii_lowercase(), correction.trim());
+        let word = typo.trim_matches(':');
+        if word.is_empty() || !word.bytes().all(|c| c.is_ascii_lowercase() || c == b'\'') {
+            panic!("autocorrect.txt:{}: typos can only have letters and `'`", line_number + 1);
+        }
+
+        let mut node = &mut root;
+        for c in typo.bytes().rev() {
+            if node.correction.is_some() {
+                panic!("autocorrect.txt:{}: `{typo}` ends with another typo", line_number + 1);
+            }
+
+            node = node.children.entry(c).or_default();
+        }
+
+        if node.correction.is_some() || !node.children.is_empty() {
+            panic!("autocorrect.txt:{}: `{typo}` overlaps another typo", line_number + 1);
+        }
+
+        node.correction = Some(corrections.len());
+        corrections.push(correction.to_string());
+    }
+
+    let mut trie = Vec::new();
+    serialize_trie_node(&root, &mut trie);
+    assert!(trie.len() <= u16::MAX as usize, "The autocorrect trie is too large");
+
+    let mut code = String::new();
+    code.push_str("// Generated by build.rs from autocorrect.txt.\n\n");
+    code.push_str(&format!("const AUTOCORRECT_TRIE: &[u8] = &{trie:?};\n\n"));
+    code.push_str("const AUTOCORRECTIONS: &[Macro] = &[\n");
+    for correction in corrections {
+        code.push_str(&format!("    &[Text({correction:?})],\n"));
+    }
+    code.push_str("];\n");
+
+    fs::write(out.join("autocorrect.rs"), code).unwrap();
+}
+
+/// Appends `node` and its children to `trie`, children after their parent.
+fn serialize_trie_node(node: &TrieNode, trie: &mut Vec<u8>) {
+    if let Some(correction) = node.correction {
+        trie.push(LEAF);
+        trie.extend_from_slice(&(correction as u16).to_le_bytes());
+        return;
+    }
+
+    let start = trie.len();
+    for c in node.children.keys() {
+        trie.extend_from_slice(&[*c, 0, 0]);
+    }
+    trie.push(0);
+
+    for (i, child) in node.children.values().enumerate() {
+        let offset = (trie.len() as u16).to_le_bytes();
+        trie[start + 3 * i + 1..start + 3 * i + 3].copy_from_slice(&offset);
+        serialize_trie_node(child, trie);
+    }
 }
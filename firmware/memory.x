@@ -1,6 +1,8 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 16K
+    /* Reserved for the settings store, see src/settings.rs */
+    SETTINGS : ORIGIN = 0x101FC000, LENGTH = 16K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
@@ -0,0 +1,173 @@
+//! Actions, which describe what each key in the layer mappings does.
+
+use crate::{
+    key_codes::{ConsumerCode, KeyCode, SystemCode},
+    key_scan::{KeyboardReport, Layer, Led, Leds},
+    macros::Macro,
+};
+
+/// Modifier bitmasks for `Action::Modified`, matching `KeyCode::modifier_bitmask`.
+pub const MOD_CTRL: u8 = 1 << 0;
+pub const MOD_SHIFT: u8 = 1 << 1;
+pub const MOD_ALT: u8 = 1 << 2;
+pub const MOD_CMD: u8 = 1 << 3;
+pub const MOD_MEH: u8 = MOD_CTRL | MOD_SHIFT | MOD_ALT;
+pub const MOD_HYPER: u8 = MOD_MEH | MOD_CMD;
+
+#[allow(unused)]
+#[derive(Copy, Clone)]
+pub enum Action {
+    /// A single key or modifier. `KeyCode::Empty` does nothing.
+    Key(KeyCode),
+
+    /// A key sent together with a `MOD_*` bitmask of modifiers, such as Ctrl+Left.
+    /// With `KeyCode::Empty` only the modifiers are sent, which is how Hyper and Meh work.
+    Modified(u8, KeyCode),
+
+    /// Activates a layer for as long as the key is held.
+    MomentaryLayer(Layer),
+
+    /// A modifier which applies to the next key when tapped, and locks when double-tapped.
+    /// Held together with another key, it is a normal modifier.
+    OneShot(KeyCode),
+
+    /// Sends a usage from the Consumer page, such as media and volume controls.
+    Consumer(ConsumerCode),
+
+    /// Sends a usage from the System Control collection, such as Sleep.
+    System(SystemCode),
+
+    /// Performs the tap dance at an index in `TAP_DANCES`.
+    TapDance(usize),
+
+    /// Plays a macro when pressed. Pressing it again while the macro plays cancels it.
+    Macro(Macro),
+
+    /// Calls a function on every scan while the key is held, which can add keys to the report.
+    Custom(fn(&mut KeyboardReport)),
+
+    /// Starts listening for a sequence from `LEADER_SEQUENCES`.
+    Leader,
+
+    /// Toggles Caps Word.
+    CapsWord,
+
+    /// Toggles auto-shift.
+    AutoShiftToggle,
+
+    /// Toggles autocorrect.
+    AutocorrectToggle,
+
+    /// Starts recording the dynamic macro in a slot, or stops any recording.
+    DynamicMacroRecord(usize),
+
+    /// Plays the dynamic macro recorded in a slot, or stops any recording.
+    DynamicMacroPlay(usize),
+
+    /// Sends the last key pressed again, with the same modifiers.
+    Repeat,
+
+    /// Sends the alternate of the last key pressed, from `ALTERNATE_REPEATS`.
+    AlternateRepeat,
+
+    /// Sends Escape, or `` ` `` while Shift or Cmd is held (so Shift gives `~`).
+    GraveEscape,
+
+    /// Sends the first key while the host has the LED on, and the second one otherwise.
+    IfLed(Led, KeyCode, KeyCode),
+
+    /// Switches to the profile at an index in `PROFILES`.
+    SelectProfile(usize),
+}
+
+impl Action {
+    /// The key sent by this action, if it is a plain `Action::Key`.
+    pub fn key(&self) -> Option<KeyCode> {
+        match *self {
+            Action::Key(KeyCode::Empty) => None,
+            Action::Key(key) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// The modifiers held by this action, if it only holds modifiers.
+    pub fn modifier_bitmask(&self) -> Option<u8> {
+        match *self {
+            Action::Key(key) | Action::OneShot(key) => key.modifier_bitmask(),
+            Action::Modified(modifiers, KeyCode::Empty) => Some(modifiers),
+            _ => None,
+        }
+    }
+
+    /// Resolves actions whose key depends on the held `modifiers` or the host's `leds` to
+    /// the `Action::Key` they send right now. Other actions are returned unchanged.
+    pub fn resolve(self, modifiers: u8, leds: Leds) -> Action {
+        // The right-hand modifiers are the left-hand bits shifted up by 4.
+        let shift_or_cmd = (MOD_SHIFT | MOD_CMD) | (MOD_SHIFT | MOD_CMD) << 4;
+
+        match self {
+            Action::GraveEscape if modifiers & shift_or_cmd != 0 => Action::Key(KeyCode::Tilde),
+            Action::GraveEscape => Action::Key(KeyCode::Escape),
+            Action::IfLed(led, on, off) => Action::Key(if leds.is_on(led) { on } else { off }),
+            action => action,
+        }
+    }
+
+    /// Whether this action only changes the meaning of other keys, like modifiers and
+    /// layer keys do.
+    pub fn is_modifier(&self) -> bool {
+        self.modifier_bitmask().is_some() || matches!(*self, Action::MomentaryLayer(_))
+    }
+}
+
+// Helpers to keep the layer mappings readable.
+
+pub const NONE: Action = Action::Key(KeyCode::Empty);
+#[allow(unused)]
+pub const MEH: Action = Action::Modified(MOD_MEH, KeyCode::Empty);
+#[allow(unused)]
+pub const HYPER: Action = Action::Modified(MOD_HYPER, KeyCode::Empty);
+
+pub const fn k(key: KeyCode) -> Action {
+    Action::Key(key)
+}
+
+pub const fn ctrl(key: KeyCode) -> Action {
+    Action::Modified(MOD_CTRL, key)
+}
+
+pub const fn shift(key: KeyCode) -> Action {
+    Action::Modified(MOD_SHIFT, key)
+}
+
+#[allow(unused)]
+pub const fn alt(key: KeyCode) -> Action {
+    Action::Modified(MOD_ALT, key)
+}
+
+pub const fn cmd(key: KeyCode) -> Action {
+    Action::Modified(MOD_CMD, key)
+}
+
+pub const fn mo(layer: Layer) -> Action {
+    Action::MomentaryLayer(layer)
+}
+
+pub const fn one_shot(modifier: KeyCode) -> Action {
+    assert!(modifier.modifier_bitmask().is_some(), "only modifiers can be one-shot");
+    Action::OneShot(modifier)
+}
+
+/// Sends `keypad` while Num Lock is on, and `key` otherwise.
+pub const fn num(keypad: KeyCode, key: KeyCode) -> Action {
+    Action::IfLed(Led::NumLock, keypad, key)
+}
+
+pub const fn consumer(code: ConsumerCode) -> Action {
+    Action::Consumer(code)
+}
+
+#[allow(unused)]
+pub const fn system(code: SystemCode) -> Action {
+    Action::System(code)
+}
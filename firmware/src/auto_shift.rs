@@ -0,0 +1,305 @@
+//! Auto-shift, which sends the shifted variant of a key when it's held down for longer
+//! than a threshold, so Shift doesn't need to be held.
+
+use crate::{
+    action::Action,
+    key_codes::KeyCode,
+    key_scan::{KeyScan, KeyboardReport},
+    NUM_COLS, NUM_ROWS,
+};
+
+/// The groups of keys which are auto-shifted when held.
+#[derive(Copy, Clone)]
+pub struct AutoShiftGroups {
+    pub letters: bool,
+    pub numbers: bool,
+    pub symbols: bool,
+}
+
+impl AutoShiftGroups {
+    fn contains(&self, key: KeyCode) -> bool {
+        (self.letters && key.is_letter())
+            || (self.numbers && key.is_digit())
+            || (self.symbols && key.is_symbol())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum AutoShiftKey {
+    Idle,
+    /// The key is held, but not yet for long enough to be shifted.
+    Pressed {
+        held_ticks: u16,
+    },
+    /// The key has been held past the threshold, and is reported with Shift until released.
+    Shifted,
+    /// The key was released before the threshold, and is reported unshifted for a few ticks.
+    Tapped {
+        remaining_ticks: u16,
+    },
+}
+
+/// `AutoShift` holds back the keys in its groups until they are either released, which
+/// sends the key as normal, or held for `timeout_ticks`, which sends it with Shift.
+///
+/// Pressing another key resolves a held key unshifted. A resolved key is put back into the
+/// scan, so the features after auto-shift (such as Caps Word, one-shot modifiers and
+/// rollover) see it pressed once it's resolved rather than when it was physically pressed.
+///
+/// It can be switched on and off at runtime with the `AutoShiftToggle` key. Keys pressed
+/// while a modifier is held, or while one-shot modifiers apply, are never auto-shifted.
+pub struct AutoShift {
+    enabled: bool,
+    groups: AutoShiftGroups,
+
+    /// The state of each key in the matrix.
+    keys: [[AutoShiftKey; NUM_ROWS]; NUM_COLS],
+
+    /// The matrix from the previous tick, used to find newly pressed keys.
+    previous_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+
+    timeout_ticks: u16,
+    tap_ticks: u16,
+}
+
+impl AutoShift {
+    pub fn new(enabled: bool, groups: AutoShiftGroups, timeout_ticks: u16, tap_ticks: u16) -> Self {
+        Self {
+            enabled,
+            groups,
+            keys: [[AutoShiftKey::Idle; NUM_ROWS]; NUM_COLS],
+            previous_matrix: [[false; NUM_ROWS]; NUM_COLS],
+            timeout_ticks,
+            tap_ticks,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Removes the `AutoShiftToggle` key and any auto-shifted keys which haven't been resolved
+    /// yet from `scan`, puts back the resolved ones, and advances the auto-shift state by one
+    /// tick.
+    ///
+    /// `one_shot_modifiers` is the bitmask of the one-shot modifiers which apply to the next
+    /// key, which count as held.
+    pub fn tick(&mut self, scan: &mut KeyScan<NUM_ROWS, NUM_COLS>, one_shot_modifiers: u8) {
+        let layer_mapping = scan.layer_mapping();
+
+        let mut modifier_held = one_shot_modifiers != 0;
+        let mut key_pressed = false;
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                let action = layer_mapping[col][row];
+                modifier_held |= scan[col][row] && action.modifier_bitmask().is_some();
+                key_pressed |= scan[col][row]
+                    && !self.previous_matrix[col][row]
+                    && !action.is_modifier()
+                    && !matches!(action, Action::Key(KeyCode::Empty));
+            }
+        }
+
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                let action = layer_mapping[col][row];
+                let pressed = scan[col][row];
+                let newly_pressed = pressed && !self.previous_matrix[col][row];
+                self.previous_matrix[col][row] = pressed;
+
+                if matches!(action, Action::AutoShiftToggle) {
+                    if newly_pressed {
+                        self.enabled = !self.enabled;
+                    }
+
+                    scan[col][row] = false;
+                    continue;
+                }
+
+                let auto_shifted = action.key().is_some_and(|key| self.groups.contains(key));
+
+                let state = &mut self.keys[col][row];
+                *state = match *state {
+                    AutoShiftKey::Idle | AutoShiftKey::Tapped { .. }
+                        if newly_pressed && auto_shifted && self.enabled && !modifier_held =>
+                    {
+                        AutoShiftKey::Pressed { held_ticks: 0 }
+                    },
+                    AutoShiftKey::Pressed { .. } if !pressed => {
+                        AutoShiftKey::Tapped { remaining_ticks: self.tap_ticks }
+                    },
+                    // Pressing another key sends this one unshifted first, so keys typed
+                    // in quick succession stay in order.
+                    AutoShiftKey::Pressed { .. } if key_pressed => AutoShiftKey::Idle,
+                    AutoShiftKey::Pressed { held_ticks } => {
+                        if held_ticks + 1 >= self.timeout_ticks {
+                            AutoShiftKey::Shifted
+                        } else {
+                            AutoShiftKey::Pressed { held_ticks: held_ticks + 1 }
+                        }
+                    },
+                    AutoShiftKey::Shifted if !pressed => AutoShiftKey::Idle,
+                    AutoShiftKey::Tapped { remaining_ticks } if remaining_ticks <= 1 => {
+                        AutoShiftKey::Idle
+                    },
+                    AutoShiftKey::Tapped { remaining_ticks } => {
+                        AutoShiftKey::Tapped { remaining_ticks: remaining_ticks - 1 }
+                    },
+                    state => state,
+                };
+
+                match *state {
+                    AutoShiftKey::Idle => {},
+                    AutoShiftKey::Pressed { .. } => scan[col][row] = false,
+                    AutoShiftKey::Shifted | AutoShiftKey::Tapped { .. } => scan[col][row] = true,
+                }
+            }
+        }
+    }
+
+    /// Adds Shift to `report` while a key held past the threshold is pressed.
+    pub fn apply(&self, report: &mut KeyboardReport) {
+        if self.keys.iter().flatten().any(|state| *state == AutoShiftKey::Shifted) {
+            report.press(KeyCode::LeftShift);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        caps_word::CapsWord, key_mapping::PROFILES, key_scan::Layer, one_shot::OneShotModifiers,
+    };
+
+    const TIMEOUT_TICKS: u16 = 10;
+    const TAP_TICKS: u16 = 2;
+
+    const GROUPS: AutoShiftGroups = AutoShiftGroups { letters: true, numbers: true, symbols: true };
+
+    /// Auto-shift, Caps Word and one-shot modifiers, run in the order of the main loop.
+    struct Pipeline {
+        auto_shift: AutoShift,
+        caps_word: CapsWord,
+        one_shot_modifiers: OneShotModifiers,
+    }
+
+    impl Pipeline {
+        fn new() -> Self {
+            Self {
+                auto_shift: AutoShift::new(true, GROUPS, TIMEOUT_TICKS, TAP_TICKS),
+                caps_word: CapsWord::new(1000),
+                one_shot_modifiers: OneShotModifiers::new(1000),
+            }
+        }
+
+        /// Runs `ticks` ticks with the keys at `positions` pressed, returning the last report.
+        fn run(&mut self, positions: &[(usize, usize)], ticks: u16) -> KeyboardReport {
+            let mut report = KeyboardReport::default();
+            for _ in 0..ticks {
+                let mut scan = KeyScan::with_pressed(&PROFILES[0], positions);
+                self.auto_shift.tick(&mut scan, self.one_shot_modifiers.modifiers());
+                self.caps_word.tick(&mut scan);
+                self.one_shot_modifiers.tick(&scan);
+
+                report = KeyboardReport::default();
+                let layer_mapping = scan.layer_mapping();
+                for col in 0..NUM_COLS {
+                    for row in 0..NUM_ROWS {
+                        if scan[col][row] {
+                            report.press_action(layer_mapping[col][row]);
+                        }
+                    }
+                }
+
+                self.one_shot_modifiers.apply(&mut report);
+                self.caps_word.apply(&mut report);
+                self.auto_shift.apply(&mut report);
+            }
+
+            report
+        }
+    }
+
+    fn key(key: KeyCode) -> (usize, usize) {
+        PROFILES[0].find(Layer::Normal, |action| action.key() == Some(key))
+    }
+
+    fn report(keys: &[KeyCode]) -> KeyboardReport {
+        let mut report = KeyboardReport::default();
+        for key in keys {
+            report.press(*key);
+        }
+
+        report
+    }
+
+    #[test]
+    fn tapped_key_is_sent_once_released() {
+        let mut pipeline = Pipeline::new();
+
+        assert_eq!(pipeline.run(&[key(KeyCode::A)], 3), report(&[]));
+        assert_eq!(pipeline.run(&[], 1), report(&[KeyCode::A]));
+        assert_eq!(pipeline.run(&[], TAP_TICKS), report(&[]));
+    }
+
+    #[test]
+    fn held_key_is_shifted() {
+        let mut pipeline = Pipeline::new();
+
+        assert_eq!(pipeline.run(&[key(KeyCode::A)], TIMEOUT_TICKS), report(&[]));
+        assert_eq!(pipeline.run(&[key(KeyCode::A)], 1), report(&[KeyCode::LeftShift, KeyCode::A]));
+        assert_eq!(pipeline.run(&[], 1), report(&[]));
+    }
+
+    #[test]
+    fn nested_presses_are_sent_in_order() {
+        let mut pipeline = Pipeline::new();
+        let (a, b) = (key(KeyCode::A), key(KeyCode::B));
+
+        assert_eq!(pipeline.run(&[a], 2), report(&[]));
+        assert_eq!(pipeline.run(&[a, b], 1), report(&[KeyCode::A]));
+        assert_eq!(pipeline.run(&[a], 1), report(&[KeyCode::A, KeyCode::B]));
+        assert_eq!(pipeline.run(&[], 1), report(&[KeyCode::B]));
+        assert_eq!(pipeline.run(&[], TAP_TICKS), report(&[]));
+    }
+
+    #[test]
+    fn caps_word_shifts_tapped_letters() {
+        let mut pipeline = Pipeline::new();
+        let fn_key = PROFILES[0]
+            .find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(Layer::Fn)));
+        let caps_word_key =
+            PROFILES[0].find(Layer::Fn, |action| matches!(action, Action::CapsWord));
+        pipeline.run(&[fn_key, caps_word_key], 1);
+        pipeline.run(&[], 1);
+
+        pipeline.run(&[key(KeyCode::A)], 3);
+        assert_eq!(pipeline.run(&[], 1), report(&[KeyCode::LeftShift, KeyCode::A]));
+    }
+
+    #[test]
+    fn one_shot_modifiers_prevent_auto_shift() {
+        let mut pipeline = Pipeline::new();
+        let fn_key = PROFILES[0]
+            .find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(Layer::Fn)));
+        let one_shot_ctrl = PROFILES[0]
+            .find(Layer::Fn, |action| matches!(action, Action::OneShot(KeyCode::LeftCtrl)));
+        pipeline.run(&[fn_key, one_shot_ctrl], 1);
+        pipeline.run(&[], 1);
+
+        assert_eq!(pipeline.run(&[key(KeyCode::A)], 1), report(&[KeyCode::LeftCtrl, KeyCode::A]));
+        assert_eq!(
+            pipeline.run(&[key(KeyCode::A)], TIMEOUT_TICKS),
+            report(&[KeyCode::LeftCtrl, KeyCode::A])
+        );
+    }
+
+    #[test]
+    fn toggled_off_keys_pass_through() {
+        let mut pipeline = Pipeline::new();
+        pipeline.auto_shift.enabled = false;
+
+        assert_eq!(pipeline.run(&[key(KeyCode::A)], TIMEOUT_TICKS + 1), report(&[KeyCode::A]));
+    }
+}
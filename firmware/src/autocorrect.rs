@@ -0,0 +1,216 @@
+//! Autocorrect, which fixes common typos as they're typed, using the list of typos in
+//! `autocorrect.txt`.
+
+use crate::{
+    action::Action,
+    key_scan::{KeyScan, KeyboardReport},
+    macros::{Macro, MacroStep::Text},
+    text_expansion::TypedBuffer,
+    typing::ascii_str,
+    NUM_COLS, NUM_ROWS,
+};
+
+// Defines `AUTOCORRECT_TRIE` and `AUTOCORRECTIONS`, see `generate_autocorrect` in `build.rs`.
+include!(concat!(env!("OUT_DIR"), "/autocorrect.rs"));
+
+/// Marks a leaf node in `AUTOCORRECT_TRIE`, followed by the index of its correction.
+const LEAF: u8 = 0x80;
+
+/// The trie character for a word boundary.
+const BOUNDARY: u8 = b':';
+
+/// Walks `AUTOCORRECT_TRIE` with the characters typed, newest first. Returns the number
+/// of letters in the typo found, and its correction.
+fn find_typo(typed: impl Iterator<Item = u8>) -> Option<(usize, Macro)> {
+    let mut node = 0;
+    let mut letters = 0;
+    for c in typed {
+        if AUTOCORRECT_TRIE[node] == LEAF {
+            break;
+        }
+
+        let entry = AUTOCORRECT_TRIE[node..]
+            .chunks(3)
+            .take_while(|entry| entry[0] != 0)
+            .find(|entry| entry[0] == c)?;
+
+        node = u16::from_le_bytes([entry[1], entry[2]]) as usize;
+        if c != BOUNDARY {
+            letters += 1;
+        }
+    }
+
+    (AUTOCORRECT_TRIE[node] == LEAF).then(|| {
+        let index = u16::from_le_bytes([AUTOCORRECT_TRIE[node + 1], AUTOCORRECT_TRIE[node + 2]]);
+        (letters, AUTOCORRECTIONS[index as usize])
+    })
+}
+
+/// `Autocorrect` watches what's typed for the typos in `autocorrect.txt`, ignoring case.
+///
+/// The key completing a typo (its last letter, or the word boundary after it) is kept from
+/// the host while it's held, and the rest of the typo is erased with Backspace before the
+/// correction and the word boundary are typed.
+///
+/// It can be switched on and off at runtime with the `AutocorrectToggle` key.
+pub struct Autocorrect {
+    enabled: bool,
+
+    /// The number of characters to erase, the correction and the text to type after it,
+    /// until taken by `take_action`.
+    action: Option<(usize, Macro, &'static str)>,
+
+    /// The matrix from the previous tick, used to find newly pressed keys.
+    previous_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+}
+
+impl Autocorrect {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, action: None, previous_matrix: [[false; NUM_ROWS]; NUM_COLS] }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Removes the `AutocorrectToggle` key from `scan`, toggling autocorrect when it's pressed.
+    pub fn tick(&mut self, scan: &mut KeyScan<NUM_ROWS, NUM_COLS>) {
+        let layer_mapping = scan.layer_mapping();
+
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                let pressed = scan[col][row];
+                let newly_pressed = pressed && !self.previous_matrix[col][row];
+                self.previous_matrix[col][row] = pressed;
+
+                if matches!(layer_mapping[col][row], Action::AutocorrectToggle) {
+                    if newly_pressed {
+                        self.enabled = !self.enabled;
+                    }
+
+                    scan[col][row] = false;
+                }
+            }
+        }
+    }
+
+    /// Watches `typed` for typos, removing the key which completed one from `report`.
+    pub fn apply(&mut self, report: &mut KeyboardReport, typed: &mut TypedBuffer) {
+        let Some(key) = typed.newly_typed() else {
+            return;
+        };
+
+        if !self.enabled {
+            return;
+        }
+
+        let chars = typed.as_bytes();
+        let last = chars[chars.len() - 1];
+        let as_trie_char = |c: &u8| match c.to_ascii_lowercase() {
+            c @ (b'a'..=b'z' | b'\'') => c,
+            _ => BOUNDARY,
+        };
+
+        // The start of typing counts as a word boundary, unless older characters were dropped.
+        let start = (!typed.is_full()).then_some(BOUNDARY);
+        let Some((letters, correction)) =
+            find_typo(chars.iter().rev().map(as_trie_char).chain(start))
+        else {
+            return;
+        };
+
+        // A typo ending with a word boundary is completed by the boundary, which is typed
+        // again after the correction.
+        let (backspaces, suffix) = match as_trie_char(&last) {
+            BOUNDARY => (letters, ascii_str(last)),
+            _ => (letters - 1, ""),
+        };
+
+        typed.suppress(report, key);
+        self.action = Some((backspaces, correction, suffix));
+    }
+
+    /// Returns the number of characters to erase, the correction to play and the text to
+    /// type after it, if a typo was just completed.
+    pub fn take_action(&mut self) -> Option<(usize, Macro, &'static str)> {
+        self.action.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_codes::KeyCode, text_expansion::TextExpander};
+
+    /// Looks for a typo at the end of `typed`, which is taken as the start of typing.
+    fn typo(typed: &str) -> Option<(usize, Macro)> {
+        let as_trie_char =
+            |c: u8| if c.is_ascii_alphabetic() { c.to_ascii_lowercase() } else { BOUNDARY };
+        find_typo(typed.bytes().rev().map(as_trie_char).chain([BOUNDARY]))
+    }
+
+    #[test]
+    fn every_typo_in_the_list_is_found() {
+        let list = include_str!("../autocorrect.txt");
+        let pairs = list
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|line| line.split_once("->").unwrap());
+
+        for (typo_text, correction) in pairs {
+            let typed = typo_text.trim().replace(':', " ");
+            let letters = typed.trim().len();
+
+            assert_eq!(
+                typo(&format!("x {typed}")),
+                Some((letters, &[Text(correction.trim())][..])),
+                "{typo_text}"
+            );
+        }
+    }
+
+    #[test]
+    fn word_boundaries_have_to_match() {
+        assert_eq!(typo("teh "), Some((3, &[Text("the")][..])));
+        assert_eq!(typo("TEH."), Some((3, &[Text("the")][..])));
+        assert_eq!(typo("teh"), None);
+        assert_eq!(typo("steh "), None);
+        assert_eq!(typo("my fucntion"), Some((8, &[Text("function")][..])));
+        assert_eq!(typo("the "), None);
+    }
+
+    /// Types `keys` with text expansion and then autocorrect watching, like the main loop.
+    fn type_keys(
+        typed: &mut TypedBuffer,
+        expander: &mut TextExpander,
+        autocorrect: &mut Autocorrect,
+        keys: &[KeyCode],
+    ) {
+        let apply = |report: &mut KeyboardReport, typed: &mut TypedBuffer| {
+            expander.apply(report, typed);
+            autocorrect.apply(report, typed);
+        };
+        crate::text_expansion::tests::type_keys(typed, apply, 0, keys);
+    }
+
+    #[test]
+    fn corrected_text_is_followed() {
+        let mut typed = TypedBuffer::new();
+        let mut expander = TextExpander::new();
+        let mut autocorrect = Autocorrect::new(true);
+
+        let keys = [KeyCode::T, KeyCode::E, KeyCode::H, KeyCode::Space];
+        type_keys(&mut typed, &mut expander, &mut autocorrect, &keys);
+        let (backspaces, correction, suffix) = autocorrect.take_action().unwrap();
+        assert_eq!((backspaces, suffix), (3, " "));
+
+        typed.replace(backspaces, correction, suffix);
+        assert_eq!(typed.as_bytes(), b"the ");
+
+        // The space typed after the correction is the word boundary before the next typo.
+        let keys = [KeyCode::A, KeyCode::D, KeyCode::N, KeyCode::Space];
+        type_keys(&mut typed, &mut expander, &mut autocorrect, &keys);
+        assert!(autocorrect.take_action().is_some());
+        assert!(expander.take_action().is_none());
+    }
+}
@@ -0,0 +1,248 @@
+//! Caps Word, which shifts letters until the end of the current word without touching
+//! the host's Caps Lock state.
+
+use crate::{
+    action::Action,
+    key_codes::KeyCode,
+    key_scan::{KeyScan, KeyboardReport},
+    NUM_COLS, NUM_ROWS,
+};
+
+/// `CapsWord` is toggled by the `CapsWord` key. While active, LeftShift is added to the
+/// report whenever a letter is the last key pressed. Digits, `-` and Backspace leave it
+/// active (so `_` can still be typed with Shift), while any word separator turns it off, as
+/// does not pressing anything for `timeout_ticks`.
+pub struct CapsWord {
+    active: bool,
+
+    /// Whether a letter is held and no other key was pressed after it, so the report is
+    /// shifted.
+    shifted: bool,
+
+    /// The number of ticks since a key was last pressed while active.
+    idle_ticks: u16,
+
+    /// The matrix from the previous tick, used to find newly pressed keys.
+    previous_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+
+    timeout_ticks: u16,
+}
+
+impl CapsWord {
+    pub fn new(timeout_ticks: u16) -> Self {
+        Self {
+            active: false,
+            shifted: false,
+            idle_ticks: 0,
+            previous_matrix: [[false; NUM_ROWS]; NUM_COLS],
+            timeout_ticks,
+        }
+    }
+
+    /// Removes the `CapsWord` key from `scan`, and updates the Caps Word state from the
+    /// keys pressed in it.
+    pub fn tick(&mut self, scan: &mut KeyScan<NUM_ROWS, NUM_COLS>) {
+        let layer_mapping = scan.layer_mapping();
+        self.idle_ticks = self.idle_ticks.saturating_add(1);
+
+        let mut letter_pressed = false;
+        let mut letter_newly_pressed = false;
+        let mut others_newly_pressed = [[false; NUM_ROWS]; NUM_COLS];
+        let mut other_newly_pressed = false;
+
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                let action = layer_mapping[col][row];
+                let pressed = scan[col][row];
+                let newly_pressed = pressed && !self.previous_matrix[col][row];
+                let letter = action.key().is_some_and(|key| key.is_letter());
+                self.previous_matrix[col][row] = pressed;
+
+                if matches!(action, Action::CapsWord) {
+                    if newly_pressed {
+                        self.active = !self.active;
+                        self.idle_ticks = 0;
+                    }
+
+                    scan[col][row] = false;
+                } else if newly_pressed {
+                    self.idle_ticks = 0;
+
+                    if action.key().is_some_and(|key| key.is_word_separator()) {
+                        self.active = false;
+                    }
+
+                    if letter {
+                        letter_newly_pressed = true;
+                    } else if !action.is_modifier()
+                        && !matches!(action, Action::Key(KeyCode::Empty))
+                    {
+                        others_newly_pressed[col][row] = true;
+                        other_newly_pressed = true;
+                    }
+                }
+
+                letter_pressed |= pressed && letter;
+            }
+        }
+
+        // Shift would apply to every key in the report, so a key pressed on the same tick as
+        // a letter is held back until the next tick, when it's sent unshifted.
+        if self.active && letter_newly_pressed && other_newly_pressed {
+            for col in 0..NUM_COLS {
+                for row in 0..NUM_ROWS {
+                    if others_newly_pressed[col][row] {
+                        scan[col][row] = false;
+                        self.previous_matrix[col][row] = false;
+                    }
+                }
+            }
+
+            other_newly_pressed = false;
+        }
+
+        if letter_newly_pressed {
+            self.shifted = true;
+        } else if other_newly_pressed {
+            self.shifted = false;
+        }
+        self.shifted &= letter_pressed;
+
+        if self.idle_ticks >= self.timeout_ticks {
+            self.active = false;
+        }
+    }
+
+    /// Shifts `report` if Caps Word is active and a letter is the last key pressed.
+    pub fn apply(&self, report: &mut KeyboardReport) {
+        if self.active && self.shifted {
+            report.press(KeyCode::LeftShift);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_mapping::PROFILES, key_scan::Layer};
+
+    const TIMEOUT_TICKS: u16 = 10;
+
+    /// Runs a tick with the keys at `positions` pressed, returning the report.
+    fn run(caps_word: &mut CapsWord, positions: &[(usize, usize)]) -> KeyboardReport {
+        let mut scan = KeyScan::with_pressed(&PROFILES[0], positions);
+        caps_word.tick(&mut scan);
+
+        let mut report = KeyboardReport::default();
+        let layer_mapping = scan.layer_mapping();
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                if scan[col][row] {
+                    report.press_action(layer_mapping[col][row]);
+                }
+            }
+        }
+
+        caps_word.apply(&mut report);
+        report
+    }
+
+    fn activate(caps_word: &mut CapsWord) {
+        let fn_key = PROFILES[0]
+            .find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(Layer::Fn)));
+        let caps_word_key =
+            PROFILES[0].find(Layer::Fn, |action| matches!(action, Action::CapsWord));
+        run(caps_word, &[fn_key, caps_word_key]);
+        run(caps_word, &[]);
+    }
+
+    fn key(key: KeyCode) -> (usize, usize) {
+        PROFILES[0].find(Layer::Normal, |action| action.key() == Some(key))
+    }
+
+    fn report(keys: &[KeyCode]) -> KeyboardReport {
+        let mut report = KeyboardReport::default();
+        for key in keys {
+            report.press(*key);
+        }
+
+        report
+    }
+
+    /// Taps each key in `keys`, returning the report from each press.
+    fn type_keys(caps_word: &mut CapsWord, keys: &[KeyCode]) -> Vec<KeyboardReport> {
+        keys.iter()
+            .map(|pressed| {
+                let report = run(caps_word, &[key(*pressed)]);
+                run(caps_word, &[]);
+                report
+            })
+            .collect()
+    }
+
+    #[test]
+    fn letters_are_shifted_until_the_end_of_the_word() {
+        let mut caps_word = CapsWord::new(TIMEOUT_TICKS);
+        activate(&mut caps_word);
+
+        let reports = type_keys(&mut caps_word, &[KeyCode::A, KeyCode::Space, KeyCode::B]);
+        assert_eq!(
+            reports,
+            [
+                report(&[KeyCode::LeftShift, KeyCode::A]),
+                report(&[KeyCode::Space]),
+                report(&[KeyCode::B])
+            ]
+        );
+    }
+
+    #[test]
+    fn digits_and_dashes_continue_the_word() {
+        let mut caps_word = CapsWord::new(TIMEOUT_TICKS);
+        activate(&mut caps_word);
+
+        let reports = type_keys(&mut caps_word, &[KeyCode::Num1, KeyCode::Minus, KeyCode::A]);
+        assert_eq!(
+            reports,
+            [
+                report(&[KeyCode::Num1]),
+                report(&[KeyCode::Minus]),
+                report(&[KeyCode::LeftShift, KeyCode::A])
+            ]
+        );
+
+        // Shift+Minus for `_` continues it too.
+        run(&mut caps_word, &[key(KeyCode::LeftShift), key(KeyCode::Minus)]);
+        run(&mut caps_word, &[]);
+        assert_eq!(
+            run(&mut caps_word, &[key(KeyCode::B)]),
+            report(&[KeyCode::LeftShift, KeyCode::B])
+        );
+    }
+
+    #[test]
+    fn keys_pressed_while_a_letter_is_held_are_not_shifted() {
+        let mut caps_word = CapsWord::new(TIMEOUT_TICKS);
+        activate(&mut caps_word);
+
+        let (a, one) = (key(KeyCode::A), key(KeyCode::Num1));
+        assert_eq!(run(&mut caps_word, &[a]), report(&[KeyCode::LeftShift, KeyCode::A]));
+        assert_eq!(run(&mut caps_word, &[a, one]), report(&[KeyCode::Num1, KeyCode::A]));
+        run(&mut caps_word, &[]);
+
+        // Pressed on the same tick, the digit follows the letter.
+        assert_eq!(run(&mut caps_word, &[a, one]), report(&[KeyCode::LeftShift, KeyCode::A]));
+        assert_eq!(run(&mut caps_word, &[a, one]), report(&[KeyCode::Num1, KeyCode::A]));
+    }
+
+    #[test]
+    fn caps_word_ends_after_the_timeout() {
+        let mut caps_word = CapsWord::new(TIMEOUT_TICKS);
+        activate(&mut caps_word);
+        for _ in 0..TIMEOUT_TICKS {
+            run(&mut caps_word, &[]);
+        }
+
+        assert_eq!(run(&mut caps_word, &[key(KeyCode::A)]), report(&[KeyCode::A]));
+    }
+}
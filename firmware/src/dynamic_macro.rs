@@ -0,0 +1,251 @@
+//! Dynamic macros, which are recorded on the keyboard itself and played back later.
+
+use crate::{action::Action, key_scan::KeyScan, NUM_COLS, NUM_ROWS};
+
+/// The number of dynamic macros which can be recorded.
+pub const DYNAMIC_MACRO_SLOTS: usize = 2;
+
+/// The number of key presses and releases each dynamic macro can hold.
+const MAX_EVENTS: usize = 256;
+
+/// A key pressed or released while recording.
+#[derive(Copy, Clone, Default)]
+struct KeyEvent {
+    col: u8,
+    row: u8,
+    pressed: bool,
+
+    /// The number of ticks since the previous event.
+    delay_ticks: u16,
+}
+
+/// The key events recorded in a slot.
+#[derive(Copy, Clone)]
+struct RecordedMacro {
+    events: [KeyEvent; MAX_EVENTS],
+    len: usize,
+}
+
+/// `DynamicMacros` records the keys pressed and released between two presses of a
+/// `DynamicMacroRecord` key, and presses and releases them again, with the same timing,
+/// when the `DynamicMacroPlay` key of the slot is pressed.
+///
+/// The keys are recorded from the debounced scan and played back into it, before any other
+/// feature sees it, so a played macro goes through the layers, tap dances, auto-shift and
+/// so on just like the original key presses.
+///
+/// Pressing any dynamic macro key while recording stops the recording. Recording stops by
+/// itself once the slot is full.
+pub struct DynamicMacros {
+    slots: [RecordedMacro; DYNAMIC_MACRO_SLOTS],
+
+    /// The slot being recorded.
+    recording: Option<usize>,
+
+    /// The slot being played, and the index of the next event to play.
+    playing: Option<(usize, usize)>,
+
+    /// The number of ticks since the last event was recorded or played.
+    elapsed_ticks: u16,
+
+    /// The keys held by the macro being played.
+    played_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+
+    /// The matrix from the previous tick, used to find pressed and released keys.
+    previous_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+}
+
+impl DynamicMacros {
+    pub fn new() -> Self {
+        Self {
+            slots: [RecordedMacro { events: [KeyEvent::default(); MAX_EVENTS], len: 0 };
+                DYNAMIC_MACRO_SLOTS],
+            recording: None,
+            playing: None,
+            elapsed_ticks: 0,
+            played_matrix: [[false; NUM_ROWS]; NUM_COLS],
+            previous_matrix: [[false; NUM_ROWS]; NUM_COLS],
+        }
+    }
+
+    /// Removes the dynamic macro keys from `scan`, starts or stops recording and playing
+    /// when they are pressed, records the keys pressed and released, and adds the keys held
+    /// by the macro being played to `scan`.
+    ///
+    /// `report_delivered` is whether the report produced on the previous tick reached the
+    /// host. A played key isn't pressed or released until then.
+    pub fn tick(&mut self, scan: &mut KeyScan<NUM_ROWS, NUM_COLS>, report_delivered: bool) {
+        let layer_mapping = scan.layer_mapping();
+        self.elapsed_ticks = self.elapsed_ticks.saturating_add(1);
+
+        let mut newly_pressed = None;
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                let action = layer_mapping[col][row];
+                let pressed = scan[col][row];
+                let changed = pressed != self.previous_matrix[col][row];
+                self.previous_matrix[col][row] = pressed;
+
+                if matches!(action, Action::DynamicMacroRecord(_) | Action::DynamicMacroPlay(_)) {
+                    if pressed && changed {
+                        newly_pressed = Some(action);
+                    }
+
+                    scan[col][row] = false;
+                } else if changed {
+                    self.record(col, row, pressed);
+                }
+            }
+        }
+
+        if let Some(action) = newly_pressed {
+            self.press(action, scan);
+        }
+
+        self.play(report_delivered);
+
+        for (matrix_column, played_column) in scan.iter_mut().zip(self.played_matrix) {
+            for (key_pressed, played) in matrix_column.iter_mut().zip(played_column) {
+                *key_pressed |= played;
+            }
+        }
+    }
+
+    /// Starts or stops recording or playing, as `action` was just pressed.
+    fn press(&mut self, action: Action, scan: &KeyScan<NUM_ROWS, NUM_COLS>) {
+        if let Some(slot) = self.recording.take() {
+            // Drop the presses of keys which are still held, such as the layer key held to
+            // reach the key which stopped the recording.
+            let recorded = &mut self.slots[slot];
+            while let Some(event) = recorded.events[..recorded.len].last() {
+                if !(event.pressed && scan[event.col as usize][event.row as usize]) {
+                    break;
+                }
+
+                recorded.len -= 1;
+            }
+
+            return;
+        }
+
+        match action {
+            Action::DynamicMacroRecord(slot) if slot < DYNAMIC_MACRO_SLOTS => {
+                self.playing = None;
+                self.slots[slot].len = 0;
+                self.recording = Some(slot);
+            },
+            Action::DynamicMacroPlay(slot) if slot < DYNAMIC_MACRO_SLOTS => {
+                self.playing = (self.slots[slot].len > 0).then_some((slot, 0));
+                self.played_matrix = [[false; NUM_ROWS]; NUM_COLS];
+                self.elapsed_ticks = 0;
+            },
+            _ => {},
+        }
+    }
+
+    fn record(&mut self, col: usize, row: usize, pressed: bool) {
+        let Some(slot) = self.recording else {
+            return;
+        };
+
+        let recorded = &mut self.slots[slot];
+        if recorded.len == MAX_EVENTS {
+            self.recording = None;
+            return;
+        }
+
+        // The time before the first key doesn't matter.
+        let delay_ticks = if recorded.len == 0 { 0 } else { self.elapsed_ticks };
+        recorded.events[recorded.len] =
+            KeyEvent { col: col as u8, row: row as u8, pressed, delay_ticks };
+        recorded.len += 1;
+        self.elapsed_ticks = 0;
+    }
+
+    /// Plays the events of the macro being played which are due, and releases its keys once
+    /// it has finished.
+    fn play(&mut self, report_delivered: bool) {
+        let Some((slot, mut index)) = self.playing else {
+            self.played_matrix = [[false; NUM_ROWS]; NUM_COLS];
+            return;
+        };
+
+        if !report_delivered {
+            return;
+        }
+
+        let recorded = &self.slots[slot];
+        while let Some(event) = recorded.events[..recorded.len].get(index) {
+            if event.delay_ticks > self.elapsed_ticks {
+                break;
+            }
+
+            self.played_matrix[event.col as usize][event.row as usize] = event.pressed;
+            self.elapsed_ticks = 0;
+            index += 1;
+        }
+
+        self.playing = (index < recorded.len).then_some((slot, index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_mapping::PROFILES, key_scan::Layer};
+
+    fn find(layer: Layer, predicate: impl Fn(Action) -> bool) -> (usize, usize) {
+        PROFILES[0].find(layer, predicate)
+    }
+
+    /// Runs one tick with the keys at `positions` held, returning the keys left in the scan.
+    fn tick(macros: &mut DynamicMacros, positions: &[(usize, usize)]) -> Vec<(usize, usize)> {
+        let mut scan = KeyScan::with_pressed(&PROFILES[0], positions);
+        macros.tick(&mut scan, true);
+
+        (0..NUM_COLS)
+            .flat_map(|col| (0..NUM_ROWS).map(move |row| (col, row)))
+            .filter(|&(col, row)| scan[col][row])
+            .collect()
+    }
+
+    #[test]
+    fn recorded_keys_are_played_back_with_their_timing() {
+        let fn_key = find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(_)));
+        let record = find(Layer::Fn, |action| matches!(action, Action::DynamicMacroRecord(0)));
+        let play = find(Layer::Fn, |action| matches!(action, Action::DynamicMacroPlay(0)));
+        let a = find(Layer::Normal, |action| action.key() == Some(crate::key_codes::KeyCode::A));
+
+        let mut macros = DynamicMacros::new();
+        assert_eq!(tick(&mut macros, &[fn_key, record]), [fn_key]);
+        tick(&mut macros, &[]);
+        tick(&mut macros, &[a]);
+        tick(&mut macros, &[a]);
+        tick(&mut macros, &[]);
+        tick(&mut macros, &[]);
+        tick(&mut macros, &[fn_key]);
+        tick(&mut macros, &[fn_key, record]);
+        assert!(macros.recording.is_none());
+        tick(&mut macros, &[]);
+
+        tick(&mut macros, &[fn_key, play]);
+        let played: Vec<_> = (0..6).map(|_| tick(&mut macros, &[])).collect();
+        assert_eq!(played, [vec![a], vec![a], vec![], vec![], vec![], vec![]]);
+        assert!(macros.playing.is_none());
+    }
+
+    #[test]
+    fn playing_waits_for_the_report_to_be_delivered() {
+        let mut macros = DynamicMacros::new();
+        macros.slots[0].events[0] = KeyEvent { col: 1, row: 2, pressed: true, delay_ticks: 0 };
+        macros.slots[0].len = 1;
+        macros.press(Action::DynamicMacroPlay(0), &KeyScan::with_pressed(&PROFILES[0], &[]));
+
+        let mut scan = KeyScan::with_pressed(&PROFILES[0], &[]);
+        macros.tick(&mut scan, false);
+        assert!(!scan[1][2]);
+
+        macros.tick(&mut scan, true);
+        assert!(scan[1][2]);
+    }
+}
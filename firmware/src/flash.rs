@@ -0,0 +1,134 @@
+//! Erasing and programming the flash chip the firmware runs from.
+//!
+//! The RP2040 executes code straight from flash (XIP), so nothing can run from flash while
+//! the chip is being erased or programmed: not the firmware, and not the USB interrupt
+//! handler. Each operation is done by a function copied to RAM, which only calls the boot
+//! ROM, with interrupts disabled until XIP is set up again. Only core 0 is running, so
+//! nothing else can touch the flash in the meantime.
+//!
+//! While interrupts are disabled USB requests go unanswered, so an erase (tens of
+//! milliseconds) should be rare.
+
+use crate::{settings::SettingsFlash, BOOT2};
+use rp2040_hal::rom_data;
+
+/// The address flash is mapped to for XIP.
+const XIP_BASE: u32 = 0x1000_0000;
+
+/// The size of the smallest area of flash which can be erased.
+pub const SECTOR_SIZE: usize = 4096;
+
+/// The size of the smallest area of flash which can be programmed.
+pub const PAGE_SIZE: usize = 256;
+
+/// Where the settings region starts, as an offset from the start of flash, and its size.
+/// These have to match `SETTINGS` in `memory.x`.
+const SETTINGS_REGION_OFFSET: u32 = 0x1FC000;
+pub const SETTINGS_REGION_SIZE: usize = 16 * 1024;
+
+// The block erase command `flash_range_erase` uses for whole 64K blocks. Erasing a single
+// sector never uses it, but the ROM needs to be told anyway.
+const BLOCK_SIZE: u32 = 65536;
+const BLOCK_ERASE_CMD: u8 = 0xD8;
+
+/// The boot ROM functions used while XIP is disabled. They're looked up beforehand, as the
+/// lookup runs from flash.
+struct RomFunctions {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+}
+
+enum Operation {
+    /// Erase the sector at an offset from the start of flash.
+    Erase(u32),
+
+    /// Program the page at an offset from the start of flash with data in RAM.
+    Program(u32, *const u8),
+}
+
+/// Erases the sector at `offset` from the start of flash, setting every byte to 0xFF.
+pub fn erase_sector(offset: u32) {
+    run(Operation::Erase(offset));
+}
+
+/// Programs the erased page at `offset` from the start of flash with `data`, which has to
+/// be in RAM.
+pub fn program_page(offset: u32, data: &[u8; PAGE_SIZE]) {
+    run(Operation::Program(offset, data.as_ptr()));
+}
+
+/// Returns the page at `offset` from the start of flash.
+pub fn read_page(offset: u32) -> &'static [u8; PAGE_SIZE] {
+    // Safety: flash is always mapped at `XIP_BASE`, except during `run`.
+    unsafe { &*((XIP_BASE + offset) as *const [u8; PAGE_SIZE]) }
+}
+
+/// The settings region of the flash chip, see `settings.rs`.
+pub struct SettingsRegion;
+
+impl SettingsRegion {
+    fn page_offset(page: usize) -> u32 {
+        SETTINGS_REGION_OFFSET + (page * PAGE_SIZE) as u32
+    }
+}
+
+impl SettingsFlash for SettingsRegion {
+    fn read_page(&self, page: usize) -> &[u8; PAGE_SIZE] {
+        read_page(Self::page_offset(page))
+    }
+
+    fn erase_sector(&mut self, page: usize) {
+        erase_sector(Self::page_offset(page));
+    }
+
+    fn program_page(&mut self, page: usize, data: &[u8; PAGE_SIZE]) {
+        program_page(Self::page_offset(page), data);
+    }
+}
+
+fn run(operation: Operation) {
+    let rom = RomFunctions {
+        connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+        flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+        flash_range_erase: rom_data::flash_range_erase::ptr(),
+        flash_range_program: rom_data::flash_range_program::ptr(),
+        flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+    };
+
+    // boot2 sets XIP up for the flash chip on the board, so a copy of it in RAM is run
+    // afterwards to get the same (fast) XIP mode back.
+    let mut boot2 = [0u32; BOOT2.len() / 4];
+    for (word, bytes) in boot2.iter_mut().zip(BOOT2.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+
+    critical_section::with(|_| {
+        // Safety: interrupts are disabled, and nothing from flash runs until XIP is back.
+        unsafe { run_from_ram(&rom, operation, boot2.as_ptr()) }
+    });
+}
+
+/// Does `operation` with XIP disabled. This is placed in `.data`, so it's copied to RAM at
+/// startup, and mustn't call anything in flash (including panics and `memcpy`).
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn run_from_ram(rom: &RomFunctions, operation: Operation, boot2: *const u32) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+
+    match operation {
+        Operation::Erase(offset) => {
+            (rom.flash_range_erase)(offset, SECTOR_SIZE, BLOCK_SIZE, BLOCK_ERASE_CMD)
+        },
+        Operation::Program(offset, data) => (rom.flash_range_program)(offset, data, PAGE_SIZE),
+    }
+
+    (rom.flash_flush_cache)();
+
+    // The low bit of the address marks boot2 as Thumb code.
+    let boot2: extern "C" fn() = core::mem::transmute(boot2 as usize + 1);
+    boot2();
+}
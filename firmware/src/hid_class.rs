@@ -1,4 +1,4 @@
-use crate::hid_descriptor::KEYBOARD_REPORT_DESCRIPTOR;
+use crate::host_os::EnumerationTrace;
 use core::marker::PhantomData;
 use usb_device::{
     class_prelude::{
@@ -11,24 +11,28 @@ use usb_device::{
 
 const USB_CLASS_HID: u8 = 0x03;
 
-const DESCRIPTOR_LEN_BYTES: [u8; 2] = (KEYBOARD_REPORT_DESCRIPTOR.len() as u16).to_le_bytes();
+const HID_DESCRIPTOR_LEN: usize = 7;
 
 // This is usually prepended with the length (including the byte for the length itself),
 // and the descriptor type, so 2 extra bytes.
-const HID_DESCRIPTOR: [u8; 7] = [
-    0x11, // bcdHID - 1.11 - LSB first
-    0x01, // bcdHID - 1.11 - LSB first
-    0x00, // bCountryCode - 0 = Not supported/specified
-    1,    // bNumDescriptors - Number of HID class descriptors to follow
-    // bDescriptorType
-    //   * 0x21      - HID
-    //   * 0x22      - Report
-    //   * 0x23      - Physical descriptor
-    //   * 0x24-0x2F - Reserved
-    0x22,                    // bDescriptorType - Report
-    DESCRIPTOR_LEN_BYTES[0], // wDescriptorLength - LSB first
-    DESCRIPTOR_LEN_BYTES[1], // wDescriptorLength - LSB first
-];
+const fn hid_descriptor(report_descriptor: &[u8]) -> [u8; HID_DESCRIPTOR_LEN] {
+    let descriptor_len_bytes = (report_descriptor.len() as u16).to_le_bytes();
+
+    [
+        0x11, // bcdHID - 1.11 - LSB first
+        0x01, // bcdHID - 1.11 - LSB first
+        0x00, // bCountryCode - 0 = Not supported/specified
+        1,    // bNumDescriptors - Number of HID class descriptors to follow
+        // bDescriptorType
+        //   * 0x21      - HID
+        //   * 0x22      - Report
+        //   * 0x23      - Physical descriptor
+        //   * 0x24-0x2F - Reserved
+        0x22,                    // bDescriptorType - Report
+        descriptor_len_bytes[0], // wDescriptorLength - LSB first
+        descriptor_len_bytes[1], // wDescriptorLength - LSB first
+    ]
+}
 
 // A HID device is composed of the following endpoints:
 // * A pair of control IN and OUT endpoints called the default endpoint
@@ -38,6 +42,12 @@ const HID_DESCRIPTOR: [u8; 7] = [
 pub struct HidClass<'a, B: UsbBus> {
     usb_interface: InterfaceNumber,
 
+    report_descriptor: &'static [u8],
+
+    // Whether this interface is a boot protocol keyboard, which BIOSes and other simple
+    // hosts can use without parsing the report descriptor.
+    boot_keyboard: bool,
+
     // The Interrupt pipe are used for:
     // * Receiving asynchronous (unrequested) data from the device.
     // * Transmitting low latency data to the device.
@@ -51,11 +61,24 @@ pub struct HidClass<'a, B: UsbBus> {
     // An Interrupt Out pipe is optional and requires an additional Endpoint descriptor
     // if declared.
     // out_endpoint: EndpointOut<'a, B>,
+
+    // The LED state from the last Output report the host sent with Set_Report, such as
+    // Caps Lock and Num Lock.
+    leds: u8,
+
+    // The requests sent by the host since the last USB reset, to detect its OS with.
+    // Every class is offered the requests addressed to the device as well as its own.
+    enumeration_trace: EnumerationTrace,
+
     _bus: PhantomData<B>,
 }
 
 impl<'a, B: UsbBus> HidClass<'a, B> {
-    pub fn new(bus_allocator: &'a UsbBusAllocator<B>) -> Self {
+    pub fn new(
+        bus_allocator: &'a UsbBusAllocator<B>,
+        report_descriptor: &'static [u8],
+        boot_keyboard: bool,
+    ) -> Self {
         let usb_interface = bus_allocator.interface();
 
         let max_packet_size = 8;
@@ -64,12 +87,30 @@ impl<'a, B: UsbBus> HidClass<'a, B> {
         let poll_interval = 1;
         let in_endpoint = bus_allocator.interrupt(max_packet_size, poll_interval);
 
-        Self { usb_interface, in_endpoint, _bus: PhantomData {} }
+        Self {
+            usb_interface,
+            report_descriptor,
+            boot_keyboard,
+            in_endpoint,
+            leds: 0,
+            enumeration_trace: EnumerationTrace::new(),
+            _bus: PhantomData {},
+        }
     }
 
     pub fn write_raw_report(&self, data: &[u8]) -> Result<usize> {
         self.in_endpoint.write(data)
     }
+
+    /// The LED state last set by the host, one bit per LED in the order of the report
+    /// descriptor (Num Lock, Caps Lock, Scroll Lock, Compose, Kana).
+    pub fn leds(&self) -> u8 {
+        self.leds
+    }
+
+    pub fn enumeration_trace(&self) -> EnumerationTrace {
+        self.enumeration_trace
+    }
 }
 
 impl<B: UsbBus> UsbClass<B> for HidClass<'_, B> {
@@ -103,12 +144,21 @@ impl<B: UsbBus> UsbClass<B> for HidClass<'_, B> {
         //     3-255 - Reserved
 
         // Write the interface descriptor
-        writer.interface(
-            self.usb_interface,
-            USB_CLASS_HID,
-            1, // Boot interface subclass
-            1, // Keyboard
-        )?;
+        if self.boot_keyboard {
+            writer.interface(
+                self.usb_interface,
+                USB_CLASS_HID,
+                1, // Boot interface subclass
+                1, // Keyboard
+            )?;
+        } else {
+            writer.interface(
+                self.usb_interface,
+                USB_CLASS_HID,
+                0, // No subclass
+                0, // None
+            )?;
+        }
 
         // Write the HID Descriptor
         writer.write(
@@ -118,7 +168,7 @@ impl<B: UsbBus> UsbClass<B> for HidClass<'_, B> {
             // 0x23      - Physical Descriptor
             // 0x24-0x2F - Reserved
             0x21, // bDescriptorType
-            &HID_DESCRIPTOR,
+            &hid_descriptor(self.report_descriptor),
         )?;
 
         // Write the descriptor for the IN endpoint
@@ -139,12 +189,37 @@ impl<B: UsbBus> UsbClass<B> for HidClass<'_, B> {
         None
     }
 
-    fn reset(&mut self) {}
+    fn reset(&mut self) {
+        // The host enumerates the keyboard again after a USB reset, and may be another host.
+        self.enumeration_trace.clear();
+    }
 
     fn poll(&mut self) {}
 
+    // Without an Interrupt Out endpoint, the host sends Output reports (the keyboard LEDs)
+    // with Set_Report requests on the Control pipe.
     fn control_out(&mut self, xfer: ControlOut<B>) {
-        let _ = xfer;
+        let request = xfer.request();
+        self.enumeration_trace.record(request);
+
+        let interface = request.index;
+        if interface != u8::from(self.usb_interface) as u16 {
+            return;
+        }
+
+        // Set_Report
+        if let (RequestType::Class, 0x09) = (request.request_type, request.request) {
+            let [_report_id, report_type] = request.value.to_le_bytes();
+
+            // Report type 0x02 - Output
+            if report_type == 0x02 {
+                if let Some(leds) = xfer.data().first() {
+                    self.leds = *leds;
+                }
+            }
+
+            xfer.accept().ok();
+        }
     }
 
     // The Control pipe is used for:
@@ -153,6 +228,7 @@ impl<B: UsbBus> UsbClass<B> for HidClass<'_, B> {
     // * Receiving data from the host.
     fn control_in(&mut self, xfer: ControlIn<B>) {
         let request = xfer.request();
+        self.enumeration_trace.record(request);
 
         let interface = request.index;
         if interface != u8::from(self.usb_interface) as u16 {
@@ -166,24 +242,26 @@ impl<B: UsbBus> UsbClass<B> for HidClass<'_, B> {
                 match descriptor_type {
                     // HID Descriptor Type
                     0x21 => {
-                        let buf: [u8; HID_DESCRIPTOR.len() + 2] = [
+                        let hid_descriptor = hid_descriptor(self.report_descriptor);
+
+                        let buf: [u8; HID_DESCRIPTOR_LEN + 2] = [
                             // Length of buf inclusive of size prefix
-                            HID_DESCRIPTOR.len() as u8 + 2,
+                            HID_DESCRIPTOR_LEN as u8 + 2,
                             0x21, // HID Descriptor type
-                            HID_DESCRIPTOR[0],
-                            HID_DESCRIPTOR[1],
-                            HID_DESCRIPTOR[2],
-                            HID_DESCRIPTOR[3],
-                            HID_DESCRIPTOR[4],
-                            HID_DESCRIPTOR[5],
-                            HID_DESCRIPTOR[6],
+                            hid_descriptor[0],
+                            hid_descriptor[1],
+                            hid_descriptor[2],
+                            hid_descriptor[3],
+                            hid_descriptor[4],
+                            hid_descriptor[5],
+                            hid_descriptor[6],
                         ];
 
                         xfer.accept_with(&buf).ok();
                     },
                     // HID Report Descriptor Type
                     0x22 => {
-                        xfer.accept_with_static(KEYBOARD_REPORT_DESCRIPTOR).ok();
+                        xfer.accept_with_static(self.report_descriptor).ok();
                     },
                     _ => {},
                 }
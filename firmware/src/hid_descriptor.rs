@@ -39,12 +39,46 @@ pub const KEYBOARD_REPORT_DESCRIPTOR: &[u8] = &[
     // Keycodes
     0x05, 0x07,        //   Usage Page (Kbrd/Keypad)
     0x19, 0x00,        //   Usage Minimum (0x00)
-    0x29, 0xDD,        //   Usage Maximum (0xDD) - TODO - double check this
+    0x29, 0xDD,        //   Usage Maximum (Keypad Hexadecimal) - the last key before the modifiers
     0x15, 0x00,        //   Logical Minimum (0)
-    0x26, 0xFF, 0x00,  //   Logical Maximum (255) - TOOD - double check max and trailing 0x00 byte
+    0x26, 0xFF, 0x00,  //   Logical Maximum (255) - 2 bytes, so it isn't read as a signed -1
     0x95, 0x06,        //   Report Count (6)
     0x75, 0x08,        //   Report Size (8)
     0x81, 0x00,        //   Input (Data,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
 
     0xC0,              // End Collection
 ];
+
+pub const CONSUMER_REPORT_ID: u8 = 0x01;
+pub const SYSTEM_REPORT_ID: u8 = 0x02;
+
+#[rustfmt::skip]
+pub const MEDIA_REPORT_DESCRIPTOR: &[u8] = &[
+    // Consumer Control
+    0x05, 0x0C,        // Usage Page (Consumer)
+    0x09, 0x01,        // Usage (Consumer Control)
+    0xA1, 0x01,        // Collection (Application)
+    0x85, CONSUMER_REPORT_ID, //   Report ID
+    0x19, 0x01,        //   Usage Minimum (Consumer Control)
+    0x2A, 0xA0, 0x02,  //   Usage Maximum (AC Desktop Show All Applications)
+    0x15, 0x01,        //   Logical Minimum (1)
+    0x26, 0xA0, 0x02,  //   Logical Maximum (0x02A0)
+    0x95, 0x01,        //   Report Count (1)
+    0x75, 0x10,        //   Report Size (16)
+    0x81, 0x00,        //   Input (Data,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0xC0,              // End Collection
+
+    // System Control
+    0x05, 0x01,        // Usage Page (Generic Desktop Ctrls)
+    0x09, 0x80,        // Usage (Sys Control)
+    0xA1, 0x01,        // Collection (Application)
+    0x85, SYSTEM_REPORT_ID, //   Report ID
+    0x19, 0x01,        //   Usage Minimum (Pointer)
+    0x2A, 0xB7, 0x00,  //   Usage Maximum (Sys Display LCD Autoscale)
+    0x15, 0x01,        //   Logical Minimum (1)
+    0x26, 0xB7, 0x00,  //   Logical Maximum (0xB7)
+    0x95, 0x01,        //   Report Count (1)
+    0x75, 0x10,        //   Report Size (16)
+    0x81, 0x00,        //   Input (Data,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0xC0,              // End Collection
+];
@@ -0,0 +1,315 @@
+//! Translation of the US legends on the keycaps to the keyboard layout the host is set
+//! to, so Shift+2 still types `@` on a host set to a JIS, UK or German layout.
+
+use crate::{
+    action::{MOD_ALT, MOD_SHIFT},
+    key_codes::KeyCode::{self, *},
+    key_scan::KeyboardReport,
+};
+use defmt::Format;
+
+/// Both Shift keys, as the right-hand modifiers are the left-hand bits shifted up by 4.
+pub const SHIFT: u8 = MOD_SHIFT | MOD_SHIFT << 4;
+/// AltGr is the right Alt key.
+const ALT_GR: u8 = MOD_ALT << 4;
+
+/// The keyboard layout the host OS is set to. The tables follow the Windows and Linux
+/// variants of each layout.
+#[allow(unused)]
+#[derive(Copy, Clone, Debug, Format, PartialEq)]
+pub enum HostLayout {
+    /// US ANSI, matching the keycaps, so nothing is translated.
+    Us,
+
+    /// Japanese (JIS 106/109).
+    Jis,
+
+    /// British (ISO).
+    Uk,
+
+    /// German (ISO QWERTZ). The dead keys `^` and `` ` `` are typed with the Unicode input
+    /// method in text, and their keys are dropped otherwise.
+    De,
+}
+
+impl HostLayout {
+    /// Returns the modifier bitmask and key which type `c` on a host set to this layout,
+    /// or `None` if `c` can't be typed with a single key press.
+    pub fn key_for(self, c: char) -> Option<(u8, KeyCode)> {
+        let us = || ascii_key(c).map(|(key, shifted)| (if shifted { MOD_SHIFT } else { 0 }, key));
+        let shift = |key| Some((MOD_SHIFT, key));
+        let alt_gr = |key| Some((ALT_GR, key));
+
+        match self {
+            HostLayout::Us => us(),
+            HostLayout::Jis => match c {
+                '"' => shift(Num2),
+                '&' => shift(Num6),
+                '\'' => shift(Num7),
+                '(' => shift(Num8),
+                ')' => shift(Num9),
+                '=' => shift(Minus),
+                '^' => Some((0, Equals)),
+                '~' => shift(Equals),
+                '@' => Some((0, LeftSquareBracket)),
+                '`' => shift(LeftSquareBracket),
+                '[' => Some((0, RightSquareBracket)),
+                '{' => shift(RightSquareBracket),
+                ']' => Some((0, NonUsHash)),
+                '}' => shift(NonUsHash),
+                '+' => shift(Semicolon),
+                ':' => Some((0, SingleQuote)),
+                '*' => shift(SingleQuote),
+                '\\' => Some((0, International1)),
+                '_' => shift(International1),
+                '|' => shift(International3),
+                '¥' => Some((0, International3)),
+                _ => us(),
+            },
+            HostLayout::Uk => match c {
+                '"' => shift(Num2),
+                '@' => shift(SingleQuote),
+                '#' => Some((0, NonUsHash)),
+                '~' => shift(NonUsHash),
+                '\\' => Some((0, NonUsBackSlash)),
+                '|' => shift(NonUsBackSlash),
+                '£' => shift(Num3),
+                '¬' => shift(Tilde),
+                '€' => alt_gr(Num4),
+                _ => us(),
+            },
+            HostLayout::De => match c {
+                'y' => Some((0, Z)),
+                'Y' => shift(Z),
+                'z' => Some((0, Y)),
+                'Z' => shift(Y),
+                '"' => shift(Num2),
+                '&' => shift(Num6),
+                '/' => shift(Num7),
+                '(' => shift(Num8),
+                ')' => shift(Num9),
+                '=' => shift(Num0),
+                '?' => shift(Minus),
+                '\\' => alt_gr(Minus),
+                '+' => Some((0, RightSquareBracket)),
+                '*' => shift(RightSquareBracket),
+                '~' => alt_gr(RightSquareBracket),
+                '#' => Some((0, NonUsHash)),
+                '\'' => shift(NonUsHash),
+                '<' => Some((0, NonUsBackSlash)),
+                '>' => shift(NonUsBackSlash),
+                '|' => alt_gr(NonUsBackSlash),
+                '@' => alt_gr(Q),
+                '{' => alt_gr(Num7),
+                '[' => alt_gr(Num8),
+                ']' => alt_gr(Num9),
+                '}' => alt_gr(Num0),
+                ';' => shift(Comma),
+                ':' => shift(Period),
+                '-' => Some((0, ForwardSlash)),
+                '_' => shift(ForwardSlash),
+                'ß' => Some((0, Minus)),
+                'ü' => Some((0, LeftSquareBracket)),
+                'Ü' => shift(LeftSquareBracket),
+                'ö' => Some((0, Semicolon)),
+                'Ö' => shift(Semicolon),
+                'ä' => Some((0, SingleQuote)),
+                'Ä' => shift(SingleQuote),
+                '§' => shift(Num3),
+                '°' => shift(Tilde),
+                '€' => alt_gr(E),
+                '^' | '`' => None,
+                _ => us(),
+            },
+        }
+    }
+}
+
+/// Returns the character printed on the keycap of `key`, with or without Shift.
+pub fn legend(key: KeyCode, shifted: bool) -> Option<char> {
+    (' '..='~').find(|c| ascii_key(*c) == Some((key, shifted)))
+}
+
+/// Rewrites the keys in `report` so their legends are typed on a host set to `layout`,
+/// changing Shift and AltGr as needed. Keys with a legend the layout has no key for are
+/// dropped, rather than typing whatever the host has on that key.
+///
+/// Shortcuts, with Ctrl, Alt or Cmd held, are left alone. Since all keys share the
+/// modifiers, the most recently pressed translated key decides Shift and AltGr.
+pub fn translate(report: &mut KeyboardReport, layout: HostLayout) {
+    if layout == HostLayout::Us || report.modifier & !SHIFT != 0 {
+        return;
+    }
+
+    let shifted = report.modifier & SHIFT != 0;
+    let mut modifier = None;
+    for keycode in report.keycodes.iter_mut() {
+        let Some(c) = KeyCode::try_from(*keycode).ok().and_then(|key| legend(key, shifted)) else {
+            continue;
+        };
+
+        match layout.key_for(c) {
+            Some((host_modifier, host_key)) => {
+                *keycode = host_key as u8;
+                modifier = Some(host_modifier);
+            },
+            None => *keycode = 0,
+        }
+    }
+
+    if let Some(modifier) = modifier {
+        report.modifier = report.modifier & !(SHIFT | ALT_GR) | modifier;
+    }
+}
+
+const LETTERS: [KeyCode; 26] =
+    [A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z];
+const DIGITS: [KeyCode; 10] = [Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9];
+
+/// Returns the key (and whether Shift is needed) which types `c` on a US keyboard layout.
+fn ascii_key(c: char) -> Option<(KeyCode, bool)> {
+    let key = match c {
+        'a'..='z' => (LETTERS[c as usize - 'a' as usize], false),
+        'A'..='Z' => (LETTERS[c as usize - 'A' as usize], true),
+        '0'..='9' => (DIGITS[c as usize - '0' as usize], false),
+        '!' => (Num1, true),
+        '@' => (Num2, true),
+        '#' => (Num3, true),
+        '$' => (Num4, true),
+        '%' => (Num5, true),
+        '^' => (Num6, true),
+        '&' => (Num7, true),
+        '*' => (Num8, true),
+        '(' => (Num9, true),
+        ')' => (Num0, true),
+        '\n' => (Enter, false),
+        '\t' => (Tab, false),
+        ' ' => (Space, false),
+        '-' => (Minus, false),
+        '_' => (Minus, true),
+        '=' => (Equals, false),
+        '+' => (Equals, true),
+        '[' => (LeftSquareBracket, false),
+        '{' => (LeftSquareBracket, true),
+        ']' => (RightSquareBracket, false),
+        '}' => (RightSquareBracket, true),
+        '\\' => (BackSlash, false),
+        '|' => (BackSlash, true),
+        ';' => (Semicolon, false),
+        ':' => (Semicolon, true),
+        '\'' => (SingleQuote, false),
+        '"' => (SingleQuote, true),
+        '`' => (Tilde, false),
+        '~' => (Tilde, true),
+        ',' => (Comma, false),
+        '<' => (Comma, true),
+        '.' => (Period, false),
+        '>' => (Period, true),
+        '/' => (ForwardSlash, false),
+        '?' => (ForwardSlash, true),
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUTS: [HostLayout; 4] =
+        [HostLayout::Us, HostLayout::Jis, HostLayout::Uk, HostLayout::De];
+
+    fn report(modifier: u8, keys: &[KeyCode]) -> KeyboardReport {
+        let mut report = KeyboardReport { modifier, ..KeyboardReport::default() };
+        for key in keys {
+            report.press(*key);
+        }
+
+        report
+    }
+
+    fn translated(layout: HostLayout, modifier: u8, keys: &[KeyCode]) -> KeyboardReport {
+        let mut report = report(modifier, keys);
+        translate(&mut report, layout);
+        report
+    }
+
+    #[test]
+    fn us_keys_type_their_legends() {
+        for c in ' '..='~' {
+            let (key, shifted) = ascii_key(c).unwrap();
+            assert_eq!(legend(key, shifted), Some(c));
+            assert_eq!(HostLayout::Us.key_for(c), Some((if shifted { MOD_SHIFT } else { 0 }, key)));
+        }
+    }
+
+    #[test]
+    fn no_two_characters_share_a_key() {
+        for layout in LAYOUTS {
+            let chars = (' '..='~').chain("¥£¬€ßüÜöÖäÄ§°".chars());
+            let mut keys: Vec<_> = chars.filter_map(|c| layout.key_for(c)).collect();
+            let len = keys.len();
+            keys.sort_by_key(|(modifier, key)| (*modifier, *key as u8));
+            keys.dedup();
+
+            assert_eq!(keys.len(), len, "{layout:?}");
+        }
+    }
+
+    #[test]
+    fn layouts_move_symbols() {
+        assert_eq!(HostLayout::Jis.key_for('@'), Some((0, LeftSquareBracket)));
+        assert_eq!(HostLayout::Jis.key_for('"'), Some((MOD_SHIFT, Num2)));
+        assert_eq!(HostLayout::Uk.key_for('#'), Some((0, NonUsHash)));
+        assert_eq!(HostLayout::Uk.key_for('€'), Some((ALT_GR, Num4)));
+        assert_eq!(HostLayout::De.key_for('z'), Some((0, Y)));
+        assert_eq!(HostLayout::De.key_for('{'), Some((ALT_GR, Num7)));
+        assert_eq!(HostLayout::De.key_for('^'), None);
+        assert_eq!(HostLayout::De.key_for('a'), Some((0, A)));
+    }
+
+    #[test]
+    fn translate_changes_shift_to_match_the_host() {
+        // Shift+2 is `@`, which JIS has on its own key.
+        assert_eq!(
+            translated(HostLayout::Jis, MOD_SHIFT, &[Num2]),
+            report(0, &[LeftSquareBracket])
+        );
+        // `'` needs Shift on JIS.
+        assert_eq!(translated(HostLayout::Jis, 0, &[SingleQuote]), report(MOD_SHIFT, &[Num7]));
+        assert_eq!(translated(HostLayout::De, 0, &[LeftSquareBracket]), report(ALT_GR, &[Num8]));
+    }
+
+    #[test]
+    fn translate_keeps_keys_without_legends() {
+        assert_eq!(
+            translated(HostLayout::Jis, MOD_SHIFT, &[Space, Tab]),
+            report(MOD_SHIFT, &[Space, Tab])
+        );
+        assert_eq!(
+            translated(HostLayout::Jis, MOD_SHIFT << 4, &[Left]),
+            report(MOD_SHIFT << 4, &[Left])
+        );
+    }
+
+    #[test]
+    fn translate_leaves_shortcuts_alone() {
+        let ctrl = crate::action::MOD_CTRL;
+        assert_eq!(translated(HostLayout::De, ctrl, &[Z]), report(ctrl, &[Z]));
+        assert_eq!(
+            translated(HostLayout::Jis, ctrl | MOD_SHIFT, &[Num2]),
+            report(ctrl | MOD_SHIFT, &[Num2])
+        );
+    }
+
+    #[test]
+    fn translate_drops_legends_the_host_cant_type() {
+        let expected = KeyboardReport {
+            modifier: MOD_SHIFT,
+            keycodes: [0, A as u8, 0, 0, 0, 0],
+            ..KeyboardReport::default()
+        };
+        assert_eq!(translated(HostLayout::De, MOD_SHIFT, &[Num6, A]), expected);
+    }
+}
@@ -0,0 +1,365 @@
+//! Host OS detection, which guesses the operating system of the host from the control
+//! requests it sends while enumerating the keyboard.
+//!
+//! Hosts don't say which OS they run, but each OS's USB stack enumerates devices in its own
+//! way. `detect` compares the requests recorded in an `EnumerationTrace` against
+//! `FINGERPRINTS`, and is kept free of any USB state so it can be checked against traces
+//! recorded from real hosts.
+//!
+//! Only GET_DESCRIPTOR requests are recorded: their order, lengths and whether the Microsoft
+//! OS descriptor is asked for. HID class requests such as SET_IDLE and SET_REPORT aren't
+//! used. The fingerprints and the traces in the tests are written from how each OS is
+//! known to enumerate devices, not from captures, so a host may be detected wrongly or not
+//! at all. Selecting a profile with a key overrides the detection.
+
+use core::ops::RangeInclusive;
+use defmt::Format;
+use usb_device::control::{Request, RequestType};
+
+/// The number of control requests recorded during enumeration.
+const MAX_OBSERVATIONS: usize = 32;
+
+/// The descriptor type of string descriptors in GET_DESCRIPTOR requests.
+const STRING_DESCRIPTOR: u8 = 0x03;
+
+/// The string index of the Microsoft OS descriptor.
+const MS_OS_DESCRIPTOR_INDEX: u8 = 0xEE;
+
+#[derive(Copy, Clone, Format, PartialEq)]
+pub enum HostOs {
+    MacOs,
+    Windows,
+    Linux,
+}
+
+/// A GET_DESCRIPTOR request seen during enumeration, with the number of bytes the host
+/// asked for. These are what tell hosts apart.
+#[derive(Copy, Clone, Format, PartialEq)]
+pub struct Observation {
+    pub descriptor_type: u8,
+    pub index: u8,
+    pub length: u16,
+}
+
+/// The first GET_DESCRIPTOR requests the host sent since the last USB reset, in order.
+#[derive(Copy, Clone)]
+pub struct EnumerationTrace {
+    observations: [Observation; MAX_OBSERVATIONS],
+    len: usize,
+}
+
+impl EnumerationTrace {
+    pub const fn new() -> Self {
+        let empty = Observation { descriptor_type: 0, index: 0, length: 0 };
+        Self { observations: [empty; MAX_OBSERVATIONS], len: 0 }
+    }
+
+    pub fn as_slice(&self) -> &[Observation] {
+        &self.observations[..self.len]
+    }
+
+    /// Records `request` if it's a GET_DESCRIPTOR request, and there's room left.
+    pub fn record(&mut self, request: &Request) {
+        if (request.request_type, request.request)
+            != (RequestType::Standard, Request::GET_DESCRIPTOR)
+        {
+            return;
+        }
+
+        let [index, descriptor_type] = request.value.to_le_bytes();
+        if self.len < MAX_OBSERVATIONS {
+            self.observations[self.len] =
+                Observation { descriptor_type, index, length: request.length };
+            self.len += 1;
+        }
+    }
+
+    /// Forgets the recorded requests, as the host is about to enumerate the keyboard again.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+/// Something which has to be true of a trace for a `Fingerprint` to match.
+enum Condition {
+    /// The number of GET_DESCRIPTOR(String) requests which asked for this many bytes is in
+    /// the range.
+    StringRequests(u16, RangeInclusive<usize>),
+
+    /// There was at least one GET_DESCRIPTOR(String) request, and all of them asked for this
+    /// many bytes.
+    AllStringRequests(u16),
+
+    /// The Microsoft OS string descriptor was asked for.
+    MsOsDescriptor,
+}
+
+impl Condition {
+    fn matches(&self, observations: &[Observation]) -> bool {
+        let string_requests = || {
+            observations
+                .iter()
+                .filter(|observation| observation.descriptor_type == STRING_DESCRIPTOR)
+                .map(|observation| observation.length)
+        };
+
+        match self {
+            Condition::StringRequests(length, count) => {
+                count.contains(&string_requests().filter(|l| l == length).count())
+            },
+            Condition::AllStringRequests(length) => {
+                string_requests().next().is_some() && string_requests().all(|l| l == *length)
+            },
+            Condition::MsOsDescriptor => observations.iter().any(|observation| {
+                observation.descriptor_type == STRING_DESCRIPTOR
+                    && observation.index == MS_OS_DESCRIPTOR_INDEX
+            }),
+        }
+    }
+}
+
+/// An OS, and the conditions which a trace of its enumeration meets.
+struct Fingerprint {
+    host_os: HostOs,
+    conditions: &'static [Condition],
+}
+
+/// The fingerprints of each OS, tried in order.
+const FINGERPRINTS: &[Fingerprint] = &[
+    // Windows asks for the MS OS descriptor the first time it sees a device.
+    Fingerprint { host_os: HostOs::Windows, conditions: &[Condition::MsOsDescriptor] },
+    // After that, it reads the language IDs with 4 bytes and the strings with 255.
+    Fingerprint {
+        host_os: HostOs::Windows,
+        conditions: &[
+            Condition::StringRequests(4, 1..=usize::MAX),
+            Condition::StringRequests(255, 2..=usize::MAX),
+        ],
+    },
+    // macOS reads the 2 byte header of a string before the whole string.
+    Fingerprint {
+        host_os: HostOs::MacOs,
+        conditions: &[
+            Condition::StringRequests(2, 2..=usize::MAX),
+            Condition::StringRequests(255, 0..=1),
+        ],
+    },
+    // Linux reads every string with 255 bytes.
+    Fingerprint { host_os: HostOs::Linux, conditions: &[Condition::AllStringRequests(255)] },
+];
+
+/// Returns the OS of the first fingerprint matching `observations`.
+pub fn detect(observations: &[Observation]) -> Option<HostOs> {
+    FINGERPRINTS
+        .iter()
+        .find(|fingerprint| {
+            fingerprint.conditions.iter().all(|condition| condition.matches(observations))
+        })
+        .map(|fingerprint| fingerprint.host_os)
+}
+
+/// `HostOsDetector` waits until the host has stopped sending control requests for a while,
+/// so enumeration is over, then detects its OS once. It detects the OS again when the host
+/// enumerates the keyboard again, such as after a KVM switches it to another host.
+pub struct HostOsDetector {
+    /// The number of ticks without new requests before the host is detected.
+    delay_ticks: u16,
+    quiet_ticks: u16,
+
+    /// The number of requests in the trace on the previous tick.
+    observed: usize,
+
+    /// Whether the host has been detected, whatever the result.
+    done: bool,
+
+    /// The detected OS, until taken by `take_action`.
+    detected: Option<HostOs>,
+}
+
+impl HostOsDetector {
+    pub fn new(delay_ticks: u16) -> Self {
+        Self { delay_ticks, quiet_ticks: 0, observed: 0, done: false, detected: None }
+    }
+
+    pub fn tick(&mut self, trace: &EnumerationTrace) {
+        let observed = trace.as_slice().len();
+
+        // The trace was cleared by a USB reset, so a new enumeration has started.
+        if observed < self.observed {
+            self.observed = 0;
+            self.quiet_ticks = 0;
+            self.done = false;
+            self.detected = None;
+        }
+
+        if self.done || observed == 0 {
+            return;
+        }
+
+        if observed != self.observed {
+            self.observed = observed;
+            self.quiet_ticks = 0;
+            return;
+        }
+
+        self.quiet_ticks += 1;
+        if self.quiet_ticks >= self.delay_ticks {
+            self.done = true;
+            self.detected = detect(trace.as_slice());
+        }
+    }
+
+    /// Returns the host's OS, once it has been detected.
+    pub fn take_action(&mut self) -> Option<HostOs> {
+        self.detected.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use usb_device::{control::Recipient, UsbDirection};
+
+    const DEVICE: u8 = 0x01;
+    const CONFIGURATION: u8 = 0x02;
+    const HID_REPORT: u8 = 0x22;
+
+    fn get_descriptor(descriptor_type: u8, index: u8, length: u16) -> Request {
+        Request {
+            direction: UsbDirection::In,
+            request_type: RequestType::Standard,
+            recipient: Recipient::Device,
+            request: Request::GET_DESCRIPTOR,
+            value: u16::from_le_bytes([index, descriptor_type]),
+            index: 0,
+            length,
+        }
+    }
+
+    fn string(index: u8, length: u16) -> Request {
+        get_descriptor(STRING_DESCRIPTOR, index, length)
+    }
+
+    fn class_request(request: u8) -> Request {
+        Request {
+            direction: UsbDirection::Out,
+            request_type: RequestType::Class,
+            recipient: Recipient::Interface,
+            request,
+            value: 0,
+            index: 0,
+            length: 0,
+        }
+    }
+
+    // The requests each OS sends when enumerating the keyboard, with the HID class requests
+    // which aren't recorded mixed in. These are written by hand rather than captured from
+    // real hosts, so replace them with captures when the fingerprints are changed.
+
+    fn windows_first_time() -> Vec<Request> {
+        vec![
+            get_descriptor(DEVICE, 0, 64),
+            get_descriptor(DEVICE, 0, 18),
+            get_descriptor(CONFIGURATION, 0, 255),
+            string(MS_OS_DESCRIPTOR_INDEX, 18),
+            string(0, 4),
+            string(2, 255),
+            class_request(0x0A),
+            get_descriptor(HID_REPORT, 0, 65),
+        ]
+    }
+
+    fn windows() -> Vec<Request> {
+        vec![
+            get_descriptor(DEVICE, 0, 64),
+            get_descriptor(DEVICE, 0, 18),
+            get_descriptor(CONFIGURATION, 0, 9),
+            get_descriptor(CONFIGURATION, 0, 59),
+            string(0, 4),
+            string(2, 255),
+            string(1, 255),
+            class_request(0x0A),
+            get_descriptor(HID_REPORT, 0, 65),
+            class_request(0x09),
+        ]
+    }
+
+    fn macos() -> Vec<Request> {
+        vec![
+            get_descriptor(DEVICE, 0, 8),
+            get_descriptor(DEVICE, 0, 18),
+            get_descriptor(CONFIGURATION, 0, 9),
+            get_descriptor(CONFIGURATION, 0, 59),
+            string(0, 2),
+            string(0, 4),
+            string(2, 2),
+            string(2, 26),
+            string(1, 2),
+            string(1, 20),
+            get_descriptor(HID_REPORT, 0, 65),
+            class_request(0x09),
+        ]
+    }
+
+    fn linux() -> Vec<Request> {
+        vec![
+            get_descriptor(DEVICE, 0, 64),
+            get_descriptor(DEVICE, 0, 18),
+            get_descriptor(CONFIGURATION, 0, 9),
+            get_descriptor(CONFIGURATION, 0, 59),
+            string(0, 255),
+            string(2, 255),
+            string(1, 255),
+            class_request(0x0A),
+            get_descriptor(HID_REPORT, 0, 65),
+            class_request(0x09),
+        ]
+    }
+
+    fn trace(requests: &[Request]) -> EnumerationTrace {
+        let mut trace = EnumerationTrace::new();
+        for request in requests {
+            trace.record(request);
+        }
+
+        trace
+    }
+
+    #[test]
+    fn detects_each_os_from_its_enumeration() {
+        assert!(detect(trace(&windows_first_time()).as_slice()) == Some(HostOs::Windows));
+        assert!(detect(trace(&windows()).as_slice()) == Some(HostOs::Windows));
+        assert!(detect(trace(&macos()).as_slice()) == Some(HostOs::MacOs));
+        assert!(detect(trace(&linux()).as_slice()) == Some(HostOs::Linux));
+        assert!(detect(trace(&[]).as_slice()).is_none());
+    }
+
+    #[test]
+    fn only_get_descriptor_requests_are_recorded() {
+        let requests = windows();
+        assert_eq!(trace(&requests).as_slice().len(), requests.len() - 2);
+    }
+
+    #[test]
+    fn detects_the_os_again_after_a_reset() {
+        let mut detector = HostOsDetector::new(2);
+        let mut trace = trace(&macos());
+        for _ in 0..3 {
+            detector.tick(&trace);
+        }
+
+        assert!(detector.take_action() == Some(HostOs::MacOs));
+
+        trace.clear();
+        detector.tick(&trace);
+        for request in linux() {
+            trace.record(&request);
+        }
+
+        for _ in 0..3 {
+            detector.tick(&trace);
+        }
+
+        assert!(detector.take_action() == Some(HostOs::Linux));
+    }
+}
@@ -1,10 +1,61 @@
+//! The usages of the HID Keyboard/Keypad page (0x07), see section 10 of the
+//! HID Usage Tables: https://usb.org/sites/default/files/hut1_5.pdf
+
+use core::{fmt, str::FromStr};
 use defmt::Format;
 
-#[allow(unused)]
-#[repr(u8)]
-#[derive(Copy, Clone, Format, PartialEq)]
-pub enum KeyCode {
-    Empty = 0x0,
+/// Defines `KeyCode` along with the lookup tables used to convert it to and from
+/// its usage ID and canonical name.
+macro_rules! key_codes {
+    ($($(#[$meta:meta])* $name:ident = $value:literal,)*) => {
+        #[allow(unused)]
+        #[repr(u8)]
+        #[derive(Copy, Clone, Debug, Format, PartialEq)]
+        pub enum KeyCode {
+            $($(#[$meta])* $name = $value,)*
+        }
+
+        impl KeyCode {
+            /// The canonical name of this key, which is the name of its variant.
+            pub const fn name(&self) -> &'static str {
+                match *self {
+                    $(KeyCode::$name => stringify!($name),)*
+                }
+            }
+
+            /// Looks up a key by its canonical name or one of its `ALIASES`, ignoring ASCII case.
+            fn from_name(name: &str) -> Option<Self> {
+                $(
+                    if name.eq_ignore_ascii_case(stringify!($name)) {
+                        return Some(KeyCode::$name);
+                    }
+                )*
+
+                ALIASES
+                    .iter()
+                    .find(|(alias, _)| name.eq_ignore_ascii_case(alias))
+                    .map(|(_, key)| *key)
+            }
+        }
+
+        impl TryFrom<u8> for KeyCode {
+            type Error = InvalidKeyCode;
+
+            fn try_from(value: u8) -> Result<Self, Self::Error> {
+                match value {
+                    $($value => Ok(KeyCode::$name),)*
+                    _ => Err(InvalidKeyCode(value)),
+                }
+            }
+        }
+    };
+}
+
+key_codes! {
+    Empty = 0x00,
+    ErrorRollOver = 0x01,
+    PostFail = 0x02,
+    ErrorUndefined = 0x03,
     A = 0x04,
     B = 0x05,
     C = 0x06,
@@ -51,6 +102,7 @@ pub enum KeyCode {
     LeftSquareBracket = 0x2F,
     RightSquareBracket = 0x30,
     BackSlash = 0x31,
+    NonUsHash = 0x32,
     Semicolon = 0x33,
     SingleQuote = 0x34,
     Tilde = 0x35,
@@ -70,41 +122,183 @@ pub enum KeyCode {
     F10 = 0x43,
     F11 = 0x44,
     F12 = 0x45,
-
-    Right = 0x4F,
-    Left = 0x50,
-    Down = 0x51,
-    Up = 0x52,
-
+    PrintScreen = 0x46,
+    ScrollLock = 0x47,
+    Pause = 0x48,
+    Insert = 0x49,
     Home = 0x4A,
     PageUp = 0x4B,
     Delete = 0x4C,
     End = 0x4D,
     PageDown = 0x4E,
+    Right = 0x4F,
+    Left = 0x50,
+    Down = 0x51,
+    Up = 0x52,
+
+    // Keypad keys
+    NumLock = 0x53,
+    KeypadSlash = 0x54,
+    KeypadAsterisk = 0x55,
+    KeypadMinus = 0x56,
+    KeypadPlus = 0x57,
+    KeypadEnter = 0x58,
+    Keypad1 = 0x59,
+    Keypad2 = 0x5A,
+    Keypad3 = 0x5B,
+    Keypad4 = 0x5C,
+    Keypad5 = 0x5D,
+    Keypad6 = 0x5E,
+    Keypad7 = 0x5F,
+    Keypad8 = 0x60,
+    Keypad9 = 0x61,
+    Keypad0 = 0x62,
+    KeypadPeriod = 0x63,
+
+    // Keys found on non-US keyboards, and other system keys
+    NonUsBackSlash = 0x64,
+    Application = 0x65,
+    Power = 0x66,
+    KeypadEquals = 0x67,
+    F13 = 0x68,
+    F14 = 0x69,
+    F15 = 0x6A,
+    F16 = 0x6B,
+    F17 = 0x6C,
+    F18 = 0x6D,
+    F19 = 0x6E,
+    F20 = 0x6F,
+    F21 = 0x70,
+    F22 = 0x71,
+    F23 = 0x72,
+    F24 = 0x73,
+    Execute = 0x74,
+    Help = 0x75,
+    Menu = 0x76,
+    Select = 0x77,
+    Stop = 0x78,
+    Again = 0x79,
+    Undo = 0x7A,
+    Cut = 0x7B,
+    Copy = 0x7C,
+    Paste = 0x7D,
+    Find = 0x7E,
 
     // Media Keys
     VolumeMute = 0x7F,
     VolumeUp = 0x80,
     VolumeDown = 0x81,
 
-    // Keypad keys
+    // Locking keys, for keyboards with physically latching switches
+    LockingCapsLock = 0x82,
+    LockingNumLock = 0x83,
+    LockingScrollLock = 0x84,
+    KeypadComma = 0x85,
+    KeypadEqualSign = 0x86,
+
+    // International and language keys, used by JIS, Korean and other layouts
+    /// Ro (ろ), the `\` and `_` key left of Right Shift on JIS keyboards.
+    International1 = 0x87,
+    /// Katakana/Hiragana (カタカナ/ひらがな) on JIS keyboards.
+    International2 = 0x88,
+    /// Yen (¥), the `¥` and `|` key left of Backspace on JIS keyboards.
+    International3 = 0x89,
+    /// Henkan (変換), right of Space on JIS keyboards.
+    International4 = 0x8A,
+    /// Muhenkan (無変換), left of Space on JIS keyboards.
+    International5 = 0x8B,
+    International6 = 0x8C,
+    International7 = 0x8D,
+    International8 = 0x8E,
+    International9 = 0x8F,
+    /// Kana (かな) on Mac JIS keyboards, Hangul/English on Korean keyboards.
+    Lang1 = 0x90,
+    /// Eisu (英数) on Mac JIS keyboards, Hanja on Korean keyboards.
+    Lang2 = 0x91,
+    Lang3 = 0x92,
+    Lang4 = 0x93,
+    Lang5 = 0x94,
+    Lang6 = 0x95,
+    Lang7 = 0x96,
+    Lang8 = 0x97,
+    Lang9 = 0x98,
+
+    // Legacy keys
+    AlternateErase = 0x99,
+    SysReq = 0x9A,
+    Cancel = 0x9B,
+    Clear = 0x9C,
+    Prior = 0x9D,
+    Return = 0x9E,
+    Separator = 0x9F,
+    Out = 0xA0,
+    Oper = 0xA1,
+    ClearAgain = 0xA2,
+    CrSel = 0xA3,
+    ExSel = 0xA4,
+
+    // Extended keypad keys
+    Keypad00 = 0xB0,
+    Keypad000 = 0xB1,
+    ThousandsSeparator = 0xB2,
+    DecimalSeparator = 0xB3,
+    CurrencyUnit = 0xB4,
+    CurrencySubUnit = 0xB5,
+    // Most hosts ignore these, use a macro with `Text("(")` to type a parenthesis.
     LeftParen = 0xB6,
     RightParen = 0xB7,
+    KeypadLeftBrace = 0xB8,
+    KeypadRightBrace = 0xB9,
+    KeypadTab = 0xBA,
+    KeypadBackspace = 0xBB,
+    KeypadA = 0xBC,
+    KeypadB = 0xBD,
+    KeypadC = 0xBE,
+    KeypadD = 0xBF,
+    KeypadE = 0xC0,
+    KeypadF = 0xC1,
+    KeypadXor = 0xC2,
+    KeypadCaret = 0xC3,
+    KeypadPercent = 0xC4,
+    KeypadLessThan = 0xC5,
+    KeypadGreaterThan = 0xC6,
+    KeypadAmpersand = 0xC7,
+    KeypadDoubleAmpersand = 0xC8,
+    KeypadPipe = 0xC9,
+    KeypadDoublePipe = 0xCA,
+    KeypadColon = 0xCB,
+    KeypadHash = 0xCC,
+    KeypadSpace = 0xCD,
+    KeypadAt = 0xCE,
+    KeypadExclamation = 0xCF,
+    KeypadMemoryStore = 0xD0,
+    KeypadMemoryRecall = 0xD1,
+    KeypadMemoryClear = 0xD2,
+    KeypadMemoryAdd = 0xD3,
+    KeypadMemorySubtract = 0xD4,
+    KeypadMemoryMultiply = 0xD5,
+    KeypadMemoryDivide = 0xD6,
+    KeypadPlusMinus = 0xD7,
+    KeypadClear = 0xD8,
+    KeypadClearEntry = 0xD9,
+    KeypadBinary = 0xDA,
+    KeypadOctal = 0xDB,
+    KeypadDecimal = 0xDC,
+    KeypadHexadecimal = 0xDD,
 
-    // Modifier keys
-    Fn = 0xF0,
-    LeftShift = 0xF1,
-    LeftCtrl = 0xF2,
-    LeftAlt = 0xF3,
-    LeftCmd = 0xF4,
-    RightCmd = 0xF5,
-    RightAlt = 0xF6,
-    RightCtrl = 0xF7,
-    RightShift = 0xF8,
+    // Modifier keys, reported in the modifier bitmask rather than as keycodes
+    LeftCtrl = 0xE0,
+    LeftShift = 0xE1,
+    LeftAlt = 0xE2,
+    LeftCmd = 0xE3,
+    RightCtrl = 0xE4,
+    RightShift = 0xE5,
+    RightAlt = 0xE6,
+    RightCmd = 0xE7,
 }
 
 impl KeyCode {
-    pub fn modifier_bitmask(&self) -> Option<u8> {
+    pub const fn modifier_bitmask(&self) -> Option<u8> {
         match *self {
             KeyCode::LeftCtrl => Some(1 << 0),
             KeyCode::LeftShift => Some(1 << 1),
@@ -118,7 +312,134 @@ impl KeyCode {
         }
     }
 
+    pub fn is_letter(&self) -> bool {
+        (KeyCode::A as u8..=KeyCode::Z as u8).contains(&(*self as u8))
+    }
+
+    pub fn is_digit(&self) -> bool {
+        (KeyCode::Num1 as u8..=KeyCode::Num0 as u8).contains(&(*self as u8))
+    }
+
+    /// Whether this is a punctuation key which types a different symbol when shifted.
+    pub fn is_symbol(&self) -> bool {
+        (KeyCode::Minus as u8..=KeyCode::ForwardSlash as u8).contains(&(*self as u8))
+    }
+
+    /// Whether this key ends a word, such as Space, Enter or punctuation other than `-`.
+    /// Letters, digits, Backspace and modifiers don't end a word.
+    pub fn is_word_separator(&self) -> bool {
+        !(self.is_letter()
+            || self.is_digit()
+            || self.is_modifier()
+            || matches!(*self, KeyCode::Empty | KeyCode::Minus | KeyCode::Backspace))
+    }
+
     pub fn is_modifier(&self) -> bool {
-        *self == KeyCode::Fn || self.modifier_bitmask().is_some()
+        self.modifier_bitmask().is_some()
+    }
+}
+
+/// Usages from the Consumer page (0x0C), sent in a separate report from the keyboard.
+#[allow(unused)]
+#[repr(u16)]
+#[derive(Copy, Clone, Format, PartialEq)]
+pub enum ConsumerCode {
+    BrightnessUp = 0x6F,
+    BrightnessDown = 0x70,
+    ScanNextTrack = 0xB5,
+    ScanPreviousTrack = 0xB6,
+    Stop = 0xB7,
+    PlayPause = 0xCD,
+    Mute = 0xE2,
+    VolumeUp = 0xE9,
+    VolumeDown = 0xEA,
+}
+
+/// Usages from the System Control collection of the Generic Desktop page (0x01).
+#[allow(unused)]
+#[repr(u16)]
+#[derive(Copy, Clone, Format, PartialEq)]
+pub enum SystemCode {
+    PowerDown = 0x81,
+    Sleep = 0x82,
+    WakeUp = 0x83,
+}
+
+/// Alternative names accepted when parsing a `KeyCode`, for keys better known by
+/// their label than by their usage name.
+const ALIASES: &[(&str, KeyCode)] = &[
+    ("Ro", KeyCode::International1),
+    ("KatakanaHiragana", KeyCode::International2),
+    ("Yen", KeyCode::International3),
+    ("Henkan", KeyCode::International4),
+    ("Muhenkan", KeyCode::International5),
+    ("Kana", KeyCode::Lang1),
+    ("Eisu", KeyCode::Lang2),
+];
+
+/// A usage ID which is reserved or not part of the Keyboard/Keypad page.
+#[derive(Copy, Clone, Debug, Format, PartialEq)]
+pub struct InvalidKeyCode(pub u8);
+
+/// A name which doesn't match the canonical name of any `KeyCode`.
+#[derive(Copy, Clone, Debug, Format, PartialEq)]
+pub struct UnknownKeyName;
+
+impl FromStr for KeyCode {
+    type Err = UnknownKeyName;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        KeyCode::from_name(name).ok_or(UnknownKeyName)
+    }
+}
+
+impl fmt::Display for KeyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_canonical_names_ignoring_case() {
+        assert_eq!("LeftShift".parse(), Ok(KeyCode::LeftShift));
+        assert_eq!("leftshift".parse(), Ok(KeyCode::LeftShift));
+        assert_eq!("NUM1".parse(), Ok(KeyCode::Num1));
+        assert_eq!("International3".parse(), Ok(KeyCode::International3));
+    }
+
+    #[test]
+    fn parses_aliases() {
+        assert_eq!("Yen".parse(), Ok(KeyCode::International3));
+        assert_eq!("muhenkan".parse(), Ok(KeyCode::International5));
+        assert_eq!("Eisu".parse(), Ok(KeyCode::Lang2));
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        assert_eq!("".parse::<KeyCode>(), Err(UnknownKeyName));
+        assert_eq!("Left Shift".parse::<KeyCode>(), Err(UnknownKeyName));
+    }
+
+    #[test]
+    fn display_round_trips_through_from_str() {
+        for value in 0..=u8::MAX {
+            if let Ok(key) = KeyCode::try_from(value) {
+                let name = key.to_string();
+                assert_eq!(name, key.name());
+                assert_eq!(name.parse(), Ok(key), "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn try_from_rejects_reserved_usages() {
+        assert_eq!(KeyCode::try_from(0x04), Ok(KeyCode::A));
+        assert_eq!(KeyCode::try_from(0xE7), Ok(KeyCode::RightCmd));
+        assert_eq!(KeyCode::try_from(0xDE), Err(InvalidKeyCode(0xDE)));
+        assert_eq!(KeyCode::try_from(0xFF), Err(InvalidKeyCode(0xFF)));
     }
 }
@@ -1,24 +1,269 @@
 use crate::{
-    key_codes::KeyCode::{self, *},
+    action::{cmd, consumer, ctrl, k, mo, num, one_shot, shift, Action, MOD_CMD, MOD_SHIFT, NONE},
+    host_layout::HostLayout,
+    host_os::HostOs,
+    key_codes::{ConsumerCode, KeyCode::*},
+    key_override::KeyOverride,
+    key_scan::{Layer, LayerRule, Led},
+    leader::LeaderSequence,
+    macros::{Macro, MacroStep::*},
+    profile::Profile,
+    repeat::AlternateRepeat,
+    space_cadet::SpaceCadetKey,
+    tap_dance::TapDance,
+    text_expansion::TextExpansion,
+    unicode::UnicodeMethod,
     NUM_COLS, NUM_ROWS,
 };
 
+#[cfg(not(feature = "jis"))]
 #[rustfmt::skip]
-pub const NORMAL_LAYER_MAPPING: [[KeyCode; NUM_COLS]; NUM_ROWS] = [
-    [Escape, F1, F2, F3, F4, F5, Empty, F6, F7, F8, F9, F10, F11, F12],
-    [Tilde, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9, Num0, Minus, Equals, Backspace],
-    [Tab, Q, W, E, R, T, Y, U, I, O, P, LeftSquareBracket, RightSquareBracket, BackSlash],
-    [CapsLock, A, S, D, F, G, H, J, K, L, Semicolon, SingleQuote, Enter, Empty],
-    [LeftShift, Empty, Z, X, C, V, B, N, M, Comma, Period, ForwardSlash, Up, Empty],
-    [Fn, LeftCtrl, LeftAlt, LeftCmd, Empty, Empty, Space, Empty, Empty, Empty, RightCmd, Left, Down, Right],
+const MACOS_NORMAL_LAYER_MAPPING: [[Action; NUM_COLS]; NUM_ROWS] = [
+    [Action::GraveEscape, k(F1), k(F2), k(F3), k(F4), k(F5), NONE, k(F6), k(F7), k(F8), k(F9), k(F10), k(F11), k(F12)],
+    [k(Tilde), k(Num1), k(Num2), k(Num3), k(Num4), k(Num5), k(Num6), k(Num7), k(Num8), k(Num9), k(Num0), k(Minus), k(Equals), k(Backspace)],
+    [k(Tab), k(Q), k(W), k(E), k(R), k(T), k(Y), k(U), k(I), k(O), k(P), k(LeftSquareBracket), k(RightSquareBracket), k(BackSlash)],
+    [k(CapsLock), k(A), k(S), k(D), k(F), k(G), k(H), k(J), k(K), k(L), k(Semicolon), k(SingleQuote), k(Enter), NONE],
+    [k(LeftShift), NONE, k(Z), k(X), k(C), k(V), k(B), k(N), k(M), k(Comma), k(Period), k(ForwardSlash), k(Up), NONE],
+    [mo(Layer::Fn), k(LeftCtrl), k(LeftAlt), k(LeftCmd), NONE, NONE, k(Space), NONE, NONE, NONE, k(RightCmd), k(Left), k(Down), k(Right)],
 ];
 
+// The Fn row has the media keys where a Mac keyboard has them. Shift, Ctrl and Alt are
+// one-shot modifiers: tap one with Fn held to apply it to the next key.
+#[cfg(not(feature = "jis"))]
 #[rustfmt::skip]
-pub const FN_LAYER_MAPPING: [[KeyCode; NUM_COLS]; NUM_ROWS] = [
-    [Escape, F1, F2, F3, F4, F5, Empty, F6, F7, F8, F9, VolumeMute, VolumeDown, VolumeUp],
-    [Tilde, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9, Num0, Minus, Equals, Backspace],
-    [Tab, Q, W, E, R, T, Y, U, I, O, P, LeftSquareBracket, RightSquareBracket, BackSlash],
-    [CapsLock, A, S, D, F, G, H, J, K, L, Semicolon, SingleQuote, Enter, Empty],
-    [LeftShift, Empty, Z, X, C, V, B, N, M, Comma, Period, ForwardSlash, Up, Empty],
-    [Empty, LeftCtrl, LeftAlt, LeftCmd, Empty, Empty, Space, Empty, Empty, Empty, RightCmd, Left, Down, Right],
+const MACOS_FN_LAYER_MAPPING: [[Action; NUM_COLS]; NUM_ROWS] = [
+    [ESCAPE_DANCE, consumer(ConsumerCode::BrightnessDown), consumer(ConsumerCode::BrightnessUp), k(F3), k(F4), k(F5), NONE, k(F6), consumer(ConsumerCode::ScanPreviousTrack), consumer(ConsumerCode::PlayPause), consumer(ConsumerCode::ScanNextTrack), consumer(ConsumerCode::Mute), consumer(ConsumerCode::VolumeDown), consumer(ConsumerCode::VolumeUp)],
+    [k(Tilde), Action::Macro(SCREENSHOT), Action::Macro(SIGN_OFF), Action::Macro(EM_DASH), Action::Macro(LEFT_ARROW), Action::Macro(RIGHT_ARROW), Action::Macro(LEFT_CORNER_BRACKET), Action::Macro(RIGHT_CORNER_BRACKET), Action::Macro(IDEOGRAPHIC_COMMA), Action::Macro(IDEOGRAPHIC_FULL_STOP), k(Num0), k(Minus), k(Equals), k(Backspace)],
+    [Action::AutoShiftToggle, k(Q), WINDOWS_PROFILE, k(E), k(R), k(T), k(Y), k(U), k(I), k(O), k(P), ctrl(Left), ctrl(Right), k(BackSlash)],
+    [Action::CapsWord, Action::AutocorrectToggle, k(S), k(D), k(F), k(G), k(H), k(J), k(K), LINUX_PROFILE, k(Semicolon), k(SingleQuote), k(Enter), NONE],
+    [one_shot(LeftShift), NONE, Action::DynamicMacroRecord(0), Action::DynamicMacroRecord(1), Action::DynamicMacroPlay(0), Action::DynamicMacroPlay(1), k(B), k(N), MACOS_PROFILE, Action::Repeat, Action::AlternateRepeat, k(ForwardSlash), k(Up), NONE],
+    [NONE, one_shot(LeftCtrl), one_shot(LeftAlt), k(LeftCmd), NONE, NONE, k(Space), NONE, NONE, NONE, Action::Leader, k(Left), k(Down), k(Right)],
+];
+
+// The JIS keymap (built with `--features jis`) is meant for hosts set to a Japanese
+// keyboard layout. It swaps BackSlash for Yen, and puts Ro, Muhenkan, Henkan and
+// Katakana/Hiragana on the Fn layer in place of `/`, LeftAlt, LeftCmd and Space.
+#[cfg(feature = "jis")]
+#[rustfmt::skip]
+const MACOS_NORMAL_LAYER_MAPPING: [[Action; NUM_COLS]; NUM_ROWS] = [
+    [Action::GraveEscape, k(F1), k(F2), k(F3), k(F4), k(F5), NONE, k(F6), k(F7), k(F8), k(F9), k(F10), k(F11), k(F12)],
+    [k(Tilde), k(Num1), k(Num2), k(Num3), k(Num4), k(Num5), k(Num6), k(Num7), k(Num8), k(Num9), k(Num0), k(Minus), k(Equals), k(Backspace)],
+    [k(Tab), k(Q), k(W), k(E), k(R), k(T), k(Y), k(U), k(I), k(O), k(P), k(LeftSquareBracket), k(RightSquareBracket), k(International3)],
+    [k(CapsLock), k(A), k(S), k(D), k(F), k(G), k(H), k(J), k(K), k(L), k(Semicolon), k(SingleQuote), k(Enter), NONE],
+    [k(LeftShift), NONE, k(Z), k(X), k(C), k(V), k(B), k(N), k(M), k(Comma), k(Period), k(ForwardSlash), k(Up), NONE],
+    [mo(Layer::Fn), k(LeftCtrl), k(LeftAlt), k(LeftCmd), NONE, NONE, k(Space), NONE, NONE, NONE, k(RightCmd), k(Left), k(Down), k(Right)],
+];
+
+// Only Shift and Ctrl are one-shot modifiers, as the Alt and Cmd keys are taken.
+#[cfg(feature = "jis")]
+#[rustfmt::skip]
+const MACOS_FN_LAYER_MAPPING: [[Action; NUM_COLS]; NUM_ROWS] = [
+    [ESCAPE_DANCE, consumer(ConsumerCode::BrightnessDown), consumer(ConsumerCode::BrightnessUp), k(F3), k(F4), k(F5), NONE, k(F6), consumer(ConsumerCode::ScanPreviousTrack), consumer(ConsumerCode::PlayPause), consumer(ConsumerCode::ScanNextTrack), consumer(ConsumerCode::Mute), consumer(ConsumerCode::VolumeDown), consumer(ConsumerCode::VolumeUp)],
+    [k(Tilde), Action::Macro(SCREENSHOT), Action::Macro(SIGN_OFF), Action::Macro(EM_DASH), Action::Macro(LEFT_ARROW), Action::Macro(RIGHT_ARROW), Action::Macro(LEFT_CORNER_BRACKET), Action::Macro(RIGHT_CORNER_BRACKET), Action::Macro(IDEOGRAPHIC_COMMA), Action::Macro(IDEOGRAPHIC_FULL_STOP), k(Num0), k(Minus), k(Equals), k(Backspace)],
+    [Action::AutoShiftToggle, k(Q), WINDOWS_PROFILE, k(E), k(R), k(T), k(Y), k(U), k(I), k(O), k(P), ctrl(Left), ctrl(Right), k(International3)],
+    [Action::CapsWord, Action::AutocorrectToggle, k(S), k(D), k(F), k(G), k(H), k(J), k(K), LINUX_PROFILE, k(Semicolon), k(SingleQuote), k(Enter), NONE],
+    [one_shot(LeftShift), NONE, Action::DynamicMacroRecord(0), Action::DynamicMacroRecord(1), Action::DynamicMacroPlay(0), Action::DynamicMacroPlay(1), k(B), k(N), MACOS_PROFILE, Action::Repeat, Action::AlternateRepeat, k(International1), k(Up), NONE],
+    [NONE, one_shot(LeftCtrl), k(International5), k(International4), NONE, NONE, k(International2), NONE, NONE, NONE, Action::Leader, k(Left), k(Down), k(Right)],
+];
+
+// Symbols on the home rows, with the shifted number row in place of the letters above them.
+// The thumb keys do nothing, as holding both of them is one way to get here.
+#[rustfmt::skip]
+const MACOS_SYMBOL_LAYER_MAPPING: [[Action; NUM_COLS]; NUM_ROWS] = [
+    [k(Escape), k(F1), k(F2), k(F3), k(F4), k(F5), NONE, k(F6), k(F7), k(F8), k(F9), k(F10), k(F11), k(F12)],
+    [k(Tilde), k(Num1), k(Num2), k(Num3), k(Num4), k(Num5), k(Num6), k(Num7), k(Num8), k(Num9), k(Num0), k(Minus), k(Equals), k(Backspace)],
+    [k(Tab), shift(Num1), shift(Num2), shift(Num3), shift(Num4), shift(Num5), shift(Num6), shift(Num7), shift(Num8), shift(Num9), shift(Num0), k(LeftSquareBracket), k(RightSquareBracket), k(BackSlash)],
+    [k(CapsLock), k(Tilde), shift(Tilde), shift(Minus), k(Minus), k(Equals), shift(Equals), shift(LeftSquareBracket), shift(RightSquareBracket), shift(BackSlash), k(Semicolon), k(SingleQuote), k(Enter), NONE],
+    [k(LeftShift), NONE, k(Z), k(X), k(C), k(V), k(B), k(N), k(M), shift(Comma), shift(Period), shift(ForwardSlash), k(Up), NONE],
+    [NONE, k(LeftCtrl), k(LeftAlt), NONE, NONE, NONE, k(Space), NONE, NONE, NONE, NONE, k(Left), k(Down), k(Right)],
+];
+
+// The PC keymap, for Windows and Linux. The Alt and Windows (Super) keys swap places, so
+// they sit where a PC keyboard has them, RightCmd becomes RightAlt (AltGr) and Fn+1 takes
+// a screenshot with PrintScreen.
+#[cfg(not(feature = "jis"))]
+#[rustfmt::skip]
+const PC_NORMAL_LAYER_MAPPING: [[Action; NUM_COLS]; NUM_ROWS] = [
+    [Action::GraveEscape, k(F1), k(F2), k(F3), k(F4), k(F5), NONE, k(F6), k(F7), k(F8), k(F9), k(F10), k(F11), k(F12)],
+    [k(Tilde), k(Num1), k(Num2), k(Num3), k(Num4), k(Num5), k(Num6), k(Num7), k(Num8), k(Num9), k(Num0), k(Minus), k(Equals), k(Backspace)],
+    [k(Tab), k(Q), k(W), k(E), k(R), k(T), k(Y), k(U), k(I), k(O), k(P), k(LeftSquareBracket), k(RightSquareBracket), k(BackSlash)],
+    [k(CapsLock), k(A), k(S), k(D), k(F), k(G), k(H), k(J), k(K), k(L), k(Semicolon), k(SingleQuote), k(Enter), NONE],
+    [k(LeftShift), NONE, k(Z), k(X), k(C), k(V), k(B), k(N), k(M), k(Comma), k(Period), k(ForwardSlash), k(Up), NONE],
+    [mo(Layer::Fn), k(LeftCtrl), k(LeftCmd), k(LeftAlt), NONE, NONE, k(Space), NONE, NONE, NONE, k(RightAlt), k(Left), k(Down), k(Right)],
+];
+
+// The Fn row has the media keys of a PC laptop: mute and volume on F1-F3, brightness on
+// F5 and F6, and the track controls on F7-F10. Shift, Ctrl and Alt are one-shot modifiers.
+#[cfg(not(feature = "jis"))]
+#[rustfmt::skip]
+const PC_FN_LAYER_MAPPING: [[Action; NUM_COLS]; NUM_ROWS] = [
+    [ESCAPE_DANCE, consumer(ConsumerCode::Mute), consumer(ConsumerCode::VolumeDown), consumer(ConsumerCode::VolumeUp), k(F4), consumer(ConsumerCode::BrightnessDown), NONE, consumer(ConsumerCode::BrightnessUp), consumer(ConsumerCode::ScanPreviousTrack), consumer(ConsumerCode::PlayPause), consumer(ConsumerCode::ScanNextTrack), consumer(ConsumerCode::Stop), k(F11), k(F12)],
+    [k(Tilde), k(PrintScreen), Action::Macro(SIGN_OFF), Action::Macro(EM_DASH), Action::Macro(LEFT_ARROW), Action::Macro(RIGHT_ARROW), Action::Macro(LEFT_CORNER_BRACKET), Action::Macro(RIGHT_CORNER_BRACKET), Action::Macro(IDEOGRAPHIC_COMMA), Action::Macro(IDEOGRAPHIC_FULL_STOP), k(Num0), k(Minus), k(Equals), k(Backspace)],
+    [Action::AutoShiftToggle, k(Q), WINDOWS_PROFILE, k(E), k(R), k(T), k(Y), k(U), k(I), k(O), k(P), ctrl(Left), ctrl(Right), k(BackSlash)],
+    [Action::CapsWord, Action::AutocorrectToggle, k(S), k(D), k(F), k(G), k(H), k(J), k(K), LINUX_PROFILE, k(Semicolon), k(SingleQuote), k(Enter), NONE],
+    [one_shot(LeftShift), NONE, Action::DynamicMacroRecord(0), Action::DynamicMacroRecord(1), Action::DynamicMacroPlay(0), Action::DynamicMacroPlay(1), k(B), k(N), MACOS_PROFILE, Action::Repeat, Action::AlternateRepeat, k(ForwardSlash), k(Up), NONE],
+    [NONE, one_shot(LeftCtrl), k(LeftCmd), one_shot(LeftAlt), NONE, NONE, k(Space), NONE, NONE, NONE, Action::Leader, k(Left), k(Down), k(Right)],
+];
+
+#[cfg(feature = "jis")]
+#[rustfmt::skip]
+const PC_NORMAL_LAYER_MAPPING: [[Action; NUM_COLS]; NUM_ROWS] = [
+    [Action::GraveEscape, k(F1), k(F2), k(F3), k(F4), k(F5), NONE, k(F6), k(F7), k(F8), k(F9), k(F10), k(F11), k(F12)],
+    [k(Tilde), k(Num1), k(Num2), k(Num3), k(Num4), k(Num5), k(Num6), k(Num7), k(Num8), k(Num9), k(Num0), k(Minus), k(Equals), k(Backspace)],
+    [k(Tab), k(Q), k(W), k(E), k(R), k(T), k(Y), k(U), k(I), k(O), k(P), k(LeftSquareBracket), k(RightSquareBracket), k(International3)],
+    [k(CapsLock), k(A), k(S), k(D), k(F), k(G), k(H), k(J), k(K), k(L), k(Semicolon), k(SingleQuote), k(Enter), NONE],
+    [k(LeftShift), NONE, k(Z), k(X), k(C), k(V), k(B), k(N), k(M), k(Comma), k(Period), k(ForwardSlash), k(Up), NONE],
+    [mo(Layer::Fn), k(LeftCtrl), k(LeftCmd), k(LeftAlt), NONE, NONE, k(Space), NONE, NONE, NONE, k(RightAlt), k(Left), k(Down), k(Right)],
+];
+
+#[cfg(feature = "jis")]
+#[rustfmt::skip]
+const PC_FN_LAYER_MAPPING: [[Action; NUM_COLS]; NUM_ROWS] = [
+    [ESCAPE_DANCE, consumer(ConsumerCode::Mute), consumer(ConsumerCode::VolumeDown), consumer(ConsumerCode::VolumeUp), k(F4), consumer(ConsumerCode::BrightnessDown), NONE, consumer(ConsumerCode::BrightnessUp), consumer(ConsumerCode::ScanPreviousTrack), consumer(ConsumerCode::PlayPause), consumer(ConsumerCode::ScanNextTrack), consumer(ConsumerCode::Stop), k(F11), k(F12)],
+    [k(Tilde), k(PrintScreen), Action::Macro(SIGN_OFF), Action::Macro(EM_DASH), Action::Macro(LEFT_ARROW), Action::Macro(RIGHT_ARROW), Action::Macro(LEFT_CORNER_BRACKET), Action::Macro(RIGHT_CORNER_BRACKET), Action::Macro(IDEOGRAPHIC_COMMA), Action::Macro(IDEOGRAPHIC_FULL_STOP), k(Num0), k(Minus), k(Equals), k(Backspace)],
+    [Action::AutoShiftToggle, k(Q), WINDOWS_PROFILE, k(E), k(R), k(T), k(Y), k(U), k(I), k(O), k(P), ctrl(Left), ctrl(Right), k(International3)],
+    [Action::CapsWord, Action::AutocorrectToggle, k(S), k(D), k(F), k(G), k(H), k(J), k(K), LINUX_PROFILE, k(Semicolon), k(SingleQuote), k(Enter), NONE],
+    [one_shot(LeftShift), NONE, Action::DynamicMacroRecord(0), Action::DynamicMacroRecord(1), Action::DynamicMacroPlay(0), Action::DynamicMacroPlay(1), k(B), k(N), MACOS_PROFILE, Action::Repeat, Action::AlternateRepeat, k(International1), k(Up), NONE],
+    [NONE, one_shot(LeftCtrl), k(International5), k(International4), NONE, NONE, k(International2), NONE, NONE, NONE, Action::Leader, k(Left), k(Down), k(Right)],
+];
+
+// Like the macOS symbol layer, but the number row types keypad digits while Num Lock is on
+// (toggled with the key left of 1), for the Alt codes and programs which want them.
+#[rustfmt::skip]
+const PC_SYMBOL_LAYER_MAPPING: [[Action; NUM_COLS]; NUM_ROWS] = [
+    [k(Escape), k(F1), k(F2), k(F3), k(F4), k(F5), NONE, k(F6), k(F7), k(F8), k(F9), k(F10), k(F11), k(F12)],
+    [k(NumLock), num(Keypad1, Num1), num(Keypad2, Num2), num(Keypad3, Num3), num(Keypad4, Num4), num(Keypad5, Num5), num(Keypad6, Num6), num(Keypad7, Num7), num(Keypad8, Num8), num(Keypad9, Num9), num(Keypad0, Num0), k(Minus), k(Equals), k(Backspace)],
+    [k(Tab), shift(Num1), shift(Num2), shift(Num3), shift(Num4), shift(Num5), shift(Num6), shift(Num7), shift(Num8), shift(Num9), shift(Num0), k(LeftSquareBracket), k(RightSquareBracket), k(BackSlash)],
+    [k(CapsLock), k(Tilde), shift(Tilde), shift(Minus), k(Minus), k(Equals), shift(Equals), shift(LeftSquareBracket), shift(RightSquareBracket), shift(BackSlash), k(Semicolon), k(SingleQuote), k(Enter), NONE],
+    [k(LeftShift), NONE, k(Z), k(X), k(C), k(V), k(B), k(N), k(M), shift(Comma), shift(Period), shift(ForwardSlash), k(Up), NONE],
+    [NONE, k(LeftCtrl), k(LeftCmd), NONE, NONE, NONE, k(Space), NONE, NONE, NONE, NONE, k(Left), k(Down), k(Right)],
+];
+
+// Fn+M, Fn+W and Fn+L select the macOS, Windows and Linux profiles.
+const MACOS_PROFILE: Action = Action::SelectProfile(0);
+const WINDOWS_PROFILE: Action = Action::SelectProfile(1);
+const LINUX_PROFILE: Action = Action::SelectProfile(2);
+
+// Complete keymaps with their settings, for each kind of host the keyboard is plugged into.
+// The first profile for the host's OS is selected when it's detected, see `host_os.rs`.
+// Set a profile's `HostLayout` to the keyboard layout its host is set to, so the legends on
+// the keycaps are translated, and its Space Cadet keys to the `*_JIS_SPACE_CADET_KEYS` to
+// switch the input method with the thumb keys.
+#[rustfmt::skip]
+pub const PROFILES: &[Profile] = &[
+    Profile::new("macOS", HostOs::MacOs, MACOS_NORMAL_LAYER_MAPPING, MACOS_FN_LAYER_MAPPING, MACOS_SYMBOL_LAYER_MAPPING, HostLayout::Us, UnicodeMethod::MacOs, MACOS_SPACE_CADET_KEYS),
+    Profile::new("Windows", HostOs::Windows, PC_NORMAL_LAYER_MAPPING, PC_FN_LAYER_MAPPING, PC_SYMBOL_LAYER_MAPPING, HostLayout::Us, UnicodeMethod::WinCompose, PC_SPACE_CADET_KEYS),
+    Profile::new("Linux", HostOs::Linux, PC_NORMAL_LAYER_MAPPING, PC_FN_LAYER_MAPPING, PC_SYMBOL_LAYER_MAPPING, HostLayout::Us, UnicodeMethod::Linux, PC_SPACE_CADET_KEYS),
+];
+
+// Layers activated by other layers, held keys and the host's LEDs, applied in order after
+// the momentary layer keys. A tri-layer would be `layers: &[Layer::A, Layer::B]`.
+#[rustfmt::skip]
+pub const LAYER_RULES: &[LayerRule] = &[
+    // Hold both thumb keys (the Cmd keys, or the Alt keys in the PC profiles) for the symbol
+    // layer. They're Space Cadet keys, so neither is sent to the host until it's pressed with
+    // another key or held on its own.
+    LayerRule { layers: &[], keys: &[LeftCmd, RightCmd], led: None, activate: Layer::Symbol },
+    LayerRule { layers: &[], keys: &[LeftAlt, RightAlt], led: None, activate: Layer::Symbol },
+    // Hold Fn while Caps Lock is on for the symbol layer.
+    LayerRule { layers: &[Layer::Fn], keys: &[], led: Some(Led::CapsLock), activate: Layer::Symbol },
+];
+
+// Capture a selection of the screen (macOS).
+const SCREENSHOT: Macro = &[Press(LeftCmd), Press(LeftShift), Tap(Num4)];
+
+// Sign off an email. Replace the placeholder with your name.
+const SIGN_OFF: Macro = &[Text("Best regards,\n"), Delay(50), Text("Your Name")];
+
+// Characters typed with the host's Unicode input method, on the Fn layer number row.
+const EM_DASH: Macro = &[Unicode("—")];
+const LEFT_ARROW: Macro = &[Unicode("←")];
+const RIGHT_ARROW: Macro = &[Unicode("→")];
+const LEFT_CORNER_BRACKET: Macro = &[Unicode("「")];
+const RIGHT_CORNER_BRACKET: Macro = &[Unicode("」")];
+const IDEOGRAPHIC_COMMA: Macro = &[Unicode("、")];
+const IDEOGRAPHIC_FULL_STOP: Macro = &[Unicode("。")];
+
+// Keys which send something else depending on how often they're tapped, performed by the
+// `Action::TapDance` keys with their index.
+const ESCAPE_DANCE: Action = Action::TapDance(0);
+
+pub const TAP_DANCES: &[TapDance] = &[
+    // Tap Fn+Escape twice for Caps Lock.
+    TapDance { taps: [Escape, CapsLock, Empty], hold: Empty, tap_hold: Empty },
+];
+
+pub const LEADER_SEQUENCES: &[LeaderSequence] = &[
+    // Leader, S: Capture a selection of the screen (macOS).
+    LeaderSequence { keys: &[S], action: SCREENSHOT },
+    // Leader, G, S: Capture the whole screen (macOS).
+    LeaderSequence { keys: &[G, S], action: &[Press(LeftCmd), Press(LeftShift), Tap(Num3)] },
+    // Leader, L: Lock the screen (macOS).
+    LeaderSequence { keys: &[L], action: &[Press(LeftCmd), Press(LeftCtrl), Tap(Q)] },
+    // Leader, U, then M, L, W or A: Switch the Unicode input method to macOS, Linux,
+    // WinCompose or Windows Alt codes.
+    LeaderSequence { keys: &[U, M], action: &[SetUnicodeMethod(UnicodeMethod::MacOs)] },
+    LeaderSequence { keys: &[U, L], action: &[SetUnicodeMethod(UnicodeMethod::Linux)] },
+    LeaderSequence { keys: &[U, W], action: &[SetUnicodeMethod(UnicodeMethod::WinCompose)] },
+    LeaderSequence { keys: &[U, A], action: &[SetUnicodeMethod(UnicodeMethod::WindowsAltCodes)] },
+];
+
+// Modifiers which type something else when tapped on their own.
+// The left thumb key only taps itself, but is held back like the others so it isn't sent
+// before the right one when they're held together for the symbol layer.
+const MACOS_SPACE_CADET_KEYS: &[SpaceCadetKey] = &[
+    SpaceCadetKey { key: LeftShift, tap: &[Text("(")] },
+    SpaceCadetKey { key: LeftCmd, tap: &[Tap(LeftCmd)] },
+    SpaceCadetKey { key: RightCmd, tap: &[Text(")")] },
+];
+
+const PC_SPACE_CADET_KEYS: &[SpaceCadetKey] = &[
+    SpaceCadetKey { key: LeftShift, tap: &[Text("(")] },
+    SpaceCadetKey { key: LeftAlt, tap: &[Tap(LeftAlt)] },
+    SpaceCadetKey { key: RightAlt, tap: &[Text(")")] },
+];
+
+// Like a Mac JIS keyboard, tapping the left thumb key on its own switches the input method
+// to Eisu (英数) and tapping the right one to Kana (かな), while holding either still acts as
+// a modifier. The thumb keys are Cmd on macOS and Alt in the PC profiles. To type Japanese
+// with a profile, give it these in `PROFILES` in place of the keys above.
+#[allow(unused)]
+const MACOS_JIS_SPACE_CADET_KEYS: &[SpaceCadetKey] = &[
+    SpaceCadetKey { key: LeftCmd, tap: &[Tap(Lang2)] },
+    SpaceCadetKey { key: RightCmd, tap: &[Tap(Lang1)] },
+];
+
+#[allow(unused)]
+const PC_JIS_SPACE_CADET_KEYS: &[SpaceCadetKey] = &[
+    SpaceCadetKey { key: LeftAlt, tap: &[Tap(Lang2)] },
+    SpaceCadetKey { key: RightAlt, tap: &[Tap(Lang1)] },
+];
+
+// Modifier and key combinations which send a different key.
+#[rustfmt::skip]
+pub const KEY_OVERRIDES: &[KeyOverride] = &[
+    KeyOverride { layer: None, modifiers: &[LeftShift], key: Backspace, replacement: Delete },
+    KeyOverride { layer: Some(Layer::Fn), modifiers: &[LeftShift], key: Up, replacement: PageUp },
+    KeyOverride { layer: Some(Layer::Fn), modifiers: &[LeftShift], key: Down, replacement: PageDown },
+    KeyOverride { layer: Some(Layer::Fn), modifiers: &[LeftShift], key: Left, replacement: Home },
+    KeyOverride { layer: Some(Layer::Fn), modifiers: &[LeftShift], key: Right, replacement: End },
+];
+
+// Abbreviations which are replaced by a snippet as soon as they're typed.
+pub const TEXT_EXPANSIONS: &[TextExpansion] = &[
+    TextExpansion { abbreviation: ";sig", expansion: SIGN_OFF },
+    TextExpansion { abbreviation: ";shrug", expansion: &[Text("¯\\_(ツ)_/¯")] },
+];
+
+// Pairs of actions which are each other's alternate, for the `AlternateRepeat` key.
+#[rustfmt::skip]
+pub const ALTERNATE_REPEATS: &[AlternateRepeat] = &[
+    AlternateRepeat { key: k(Up), alternate: k(Down) },
+    AlternateRepeat { key: k(Left), alternate: k(Right) },
+    AlternateRepeat { key: k(PageUp), alternate: k(PageDown) },
+    AlternateRepeat { key: k(Home), alternate: k(End) },
+    AlternateRepeat { key: ctrl(Left), alternate: ctrl(Right) },
+    // Undo and redo.
+    AlternateRepeat { key: ctrl(Z), alternate: ctrl(Y) },
+    AlternateRepeat { key: cmd(Z), alternate: Action::Modified(MOD_CMD | MOD_SHIFT, Z) },
 ];
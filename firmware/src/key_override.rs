@@ -0,0 +1,106 @@
+//! Key overrides, which replace a key with another while certain modifiers are held,
+//! for example Shift+Backspace sending Delete.
+
+use crate::{
+    key_codes::KeyCode,
+    key_mapping::KEY_OVERRIDES,
+    key_scan::{KeyboardReport, Layer},
+};
+
+/// A modifier and key combination which sends a different key, see `KEY_OVERRIDES` in
+/// `key_mapping.rs`.
+pub struct KeyOverride {
+    /// The layer the override applies to, or `None` for every layer.
+    pub layer: Option<Layer>,
+
+    /// The modifiers which must all be held to trigger the override. Any other held
+    /// modifiers are passed through as normal.
+    pub modifiers: &'static [KeyCode],
+
+    /// The key which triggers the override.
+    pub key: KeyCode,
+
+    /// The key sent instead of `key`.
+    pub replacement: KeyCode,
+}
+
+impl KeyOverride {
+    fn modifier_bitmask(&self) -> u8 {
+        self.modifiers.iter().filter_map(KeyCode::modifier_bitmask).fold(0, |mask, bit| mask | bit)
+    }
+}
+
+/// Applies the first matching entry of `KEY_OVERRIDES` to `report`, replacing the
+/// triggering key and removing the triggering modifiers from it.
+pub fn apply(report: &mut KeyboardReport, layer: Layer) {
+    for key_override in KEY_OVERRIDES {
+        if key_override.layer.is_some_and(|override_layer| override_layer != layer) {
+            continue;
+        }
+
+        let modifiers = key_override.modifier_bitmask();
+        if report.modifier & modifiers != modifiers {
+            continue;
+        }
+
+        if let Some(slot) = report.keycodes.iter_mut().find(|slot| **slot == key_override.key as u8)
+        {
+            *slot = key_override.replacement as u8;
+            report.modifier &= !modifiers;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(keys: &[KeyCode]) -> KeyboardReport {
+        let mut report = KeyboardReport::default();
+        for key in keys {
+            report.press(*key);
+        }
+
+        report
+    }
+
+    fn overridden(keys: &[KeyCode], layer: Layer) -> KeyboardReport {
+        let mut report = report(keys);
+        apply(&mut report, layer);
+        report
+    }
+
+    #[test]
+    fn override_replaces_the_key_and_its_modifiers() {
+        assert_eq!(
+            overridden(&[KeyCode::LeftShift, KeyCode::Backspace], Layer::Normal),
+            report(&[KeyCode::Delete])
+        );
+    }
+
+    #[test]
+    fn other_modifiers_are_kept() {
+        assert_eq!(
+            overridden(&[KeyCode::LeftCtrl, KeyCode::LeftShift, KeyCode::Backspace], Layer::Normal),
+            report(&[KeyCode::LeftCtrl, KeyCode::Delete])
+        );
+    }
+
+    #[test]
+    fn reports_without_a_match_are_unchanged() {
+        let unchanged = [
+            &[KeyCode::Backspace][..],
+            &[KeyCode::LeftCtrl, KeyCode::Backspace],
+            &[KeyCode::LeftShift, KeyCode::A],
+        ];
+        for keys in unchanged {
+            assert_eq!(overridden(keys, Layer::Normal), report(keys));
+        }
+
+        // Shift+Up only sends Page Up on the Fn layer.
+        let keys = [KeyCode::LeftShift, KeyCode::Up];
+        assert_eq!(overridden(&keys, Layer::Normal), report(&keys));
+        assert_eq!(overridden(&keys, Layer::Fn), report(&[KeyCode::PageUp]));
+    }
+}
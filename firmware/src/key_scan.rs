@@ -1,8 +1,14 @@
 use crate::{
-    key_mapping::{FN_LAYER_MAPPING, NORMAL_LAYER_MAPPING},
+    action::Action,
+    hid_descriptor::{CONSUMER_REPORT_ID, SYSTEM_REPORT_ID},
+    key_mapping::LAYER_RULES,
+    profile::Profile,
     NUM_COLS, NUM_ROWS,
 };
-use core::{convert::Infallible, ops::Deref};
+use core::{
+    convert::Infallible,
+    ops::{Deref, DerefMut},
+};
 
 use cortex_m::delay::Delay;
 use embedded_hal::digital::InputPin;
@@ -12,6 +18,12 @@ use crate::{debounce::Debounce, key_codes::KeyCode};
 #[derive(Clone, Copy)]
 pub struct KeyScan<const NUM_ROWS: usize, const NUM_COLS: usize> {
     matrix: [[bool; NUM_ROWS]; NUM_COLS],
+
+    /// The keyboard LEDs as set by the host at the time of the scan.
+    leds: Leds,
+
+    /// The profile active at the time of the scan, which has the layer mappings.
+    profile: &'static Profile,
 }
 
 impl<const NUM_ROWS: usize, const NUM_COLS: usize> Deref for KeyScan<NUM_ROWS, NUM_COLS> {
@@ -22,12 +34,20 @@ impl<const NUM_ROWS: usize, const NUM_COLS: usize> Deref for KeyScan<NUM_ROWS, N
     }
 }
 
+impl<const NUM_ROWS: usize, const NUM_COLS: usize> DerefMut for KeyScan<NUM_ROWS, NUM_COLS> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.matrix
+    }
+}
+
 impl<const NUM_ROWS: usize, const NUM_COLS: usize> KeyScan<NUM_ROWS, NUM_COLS> {
     pub fn scan(
         rows: &mut [&mut dyn InputPin<Error = Infallible>; NUM_ROWS],
         columns: &mut [&mut dyn embedded_hal::digital::OutputPin<Error = Infallible>; NUM_COLS],
         delay: &mut Delay,
         debounce: &mut Debounce<NUM_ROWS, NUM_COLS>,
+        leds: Leds,
+        profile: &'static Profile,
     ) -> Self {
         let mut raw_matrix = [[false; NUM_ROWS]; NUM_COLS];
 
@@ -43,11 +63,139 @@ impl<const NUM_ROWS: usize, const NUM_COLS: usize> KeyScan<NUM_ROWS, NUM_COLS> {
         }
 
         let matrix = debounce.report_and_tick(&raw_matrix);
-        Self { matrix }
+        Self { matrix, leds, profile }
+    }
+
+    pub fn leds(&self) -> Leds {
+        self.leds
+    }
+
+    /// The profile active at the time of the scan.
+    pub fn profile(&self) -> &'static Profile {
+        self.profile
+    }
+
+    /// Builds a scan of `profile` with only the keys at the (column, row) `positions`
+    /// pressed, for tests.
+    #[cfg(test)]
+    pub fn with_pressed(profile: &'static Profile, positions: &[(usize, usize)]) -> Self {
+        let mut matrix = [[false; NUM_ROWS]; NUM_COLS];
+        for &(col, row) in positions {
+            matrix[col][row] = true;
+        }
+
+        Self { matrix, leds: Leds::default(), profile }
+    }
+}
+
+impl KeyScan<NUM_ROWS, NUM_COLS> {
+    /// Returns the layer selected by the keys pressed in this scan.
+    ///
+    /// The layers of any `MomentaryLayer` keys held in the active layers are active, as are
+    /// those of the `LAYER_RULES` which apply, until no more layers become active. The
+    /// highest active layer (in the order of `Layer::ALL`) is selected.
+    pub fn layer(&self) -> Layer {
+        let is_active = |active: u8, layer: Layer| active & (1 << layer as u8) != 0;
+        let mut active = 1 << Layer::Normal as u8;
+
+        loop {
+            let mut next = active;
+
+            for layer in Layer::ALL.into_iter().filter(|layer| is_active(active, *layer)) {
+                for (matrix_column, mapping_column) in
+                    self.matrix.iter().zip(self.profile.layer_mapping(layer))
+                {
+                    for (key_pressed, mapping_row) in matrix_column.iter().zip(mapping_column) {
+                        if let (Action::MomentaryLayer(layer), true) = (mapping_row, *key_pressed) {
+                            next |= 1 << layer as u8;
+                        }
+                    }
+                }
+            }
+
+            for rule in LAYER_RULES {
+                let layers_active = rule.layers.iter().all(|layer| is_active(next, *layer));
+                let keys_held = rule.keys.iter().all(|key| self.is_held(*key));
+                let led_on = rule.led.is_none_or(|led| self.leds.is_on(led));
+
+                if layers_active && keys_held && led_on {
+                    next |= 1 << rule.activate as u8;
+                }
+            }
+
+            if next == active {
+                break;
+            }
+
+            active = next;
+        }
+
+        Layer::ALL.into_iter().rev().find(|layer| is_active(active, *layer)).unwrap()
+    }
+
+    /// Whether a key which sends `key` in the normal layer is pressed.
+    fn is_held(&self, key: KeyCode) -> bool {
+        self.matrix
+            .iter()
+            .zip(self.profile.layer_mapping(Layer::Normal))
+            .flat_map(|(matrix_column, mapping_column)| matrix_column.iter().zip(mapping_column))
+            .any(|(key_pressed, mapping_row)| *key_pressed && mapping_row.key() == Some(key))
+    }
+
+    /// Returns the layer mapping selected by the keys pressed in this scan.
+    pub fn layer_mapping(&self) -> [[Action; NUM_ROWS]; NUM_COLS] {
+        self.profile.layer_mapping(self.layer())
     }
 }
 
 #[derive(Copy, Clone, PartialEq)]
+pub enum Layer {
+    Normal,
+    Fn,
+    Symbol,
+}
+
+impl Layer {
+    /// Every layer, from lowest to highest priority.
+    pub const ALL: [Layer; 3] = [Layer::Normal, Layer::Fn, Layer::Symbol];
+}
+
+/// Activates a layer while all of `layers` are active, all of `keys` are held and the host
+/// has `led` on, see `LAYER_RULES` in `key_mapping.rs`.
+///
+/// For example, a tri-layer rule activates a third layer while two momentary layers are
+/// both held, and a rule with only an `led` activates a layer while Caps Lock is on. `keys`
+/// are found by what they send in the normal layer, so the layer's mapping can give them
+/// another meaning.
+pub struct LayerRule {
+    pub layers: &'static [Layer],
+    pub keys: &'static [KeyCode],
+    pub led: Option<Led>,
+    pub activate: Layer,
+}
+
+/// A keyboard LED, in the order of the bits in the LED Output report.
+#[allow(unused)]
+#[derive(Copy, Clone)]
+pub enum Led {
+    NumLock,
+    CapsLock,
+    ScrollLock,
+    Compose,
+    Kana,
+}
+
+/// The keyboard LEDs as last set by the host.
+#[derive(Copy, Clone, Default)]
+pub struct Leds(pub u8);
+
+impl Leds {
+    pub fn is_on(self, led: Led) -> bool {
+        self.0 & (1 << led as u8) != 0
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct KeyboardReport {
     pub modifier: u8,
     pub reserved: u8,
@@ -69,62 +217,91 @@ impl KeyboardReport {
             self.keycodes[5],
         ]
     }
-}
 
-impl<const NUM_ROWS: usize, const NUM_COLS: usize> From<KeyScan<NUM_ROWS, NUM_COLS>>
-    for KeyboardReport
-{
-    fn from(scan: KeyScan<NUM_ROWS, NUM_COLS>) -> Self {
-        let mut keycodes = [0u8; 6];
-        let mut keycode_index = 0;
-        let mut modifier = 0;
+    /// Adds `key` to the report, either as a modifier bit or in the first free keycode slot.
+    /// Keys which don't fit in the six keycode slots are dropped.
+    pub fn press(&mut self, key: KeyCode) {
+        if let Some(bitmask) = key.modifier_bitmask() {
+            self.modifier |= bitmask;
+        } else if let Some(slot) = self.keycodes.iter_mut().find(|slot| **slot == 0) {
+            *slot = key as u8;
+        }
+    }
 
-        let mut push_keycode = |key| {
-            if keycode_index < keycodes.len() {
-                keycodes[keycode_index] = key;
-                keycode_index += 1;
-            }
-        };
+    /// Adds the keys and modifiers sent by `action` to the report. Actions which don't
+    /// send keyboard usages are ignored.
+    pub fn press_action(&mut self, action: Action) {
+        match action {
+            Action::Key(key) | Action::OneShot(key) => self.press(key),
+            Action::Modified(modifiers, key) => {
+                self.modifier |= modifiers;
+                self.press(key);
+            },
+            _ => {},
+        }
+    }
 
-        // First scan for any function keys being pressed
-        let mut layer_mapping = TRANSPOSED_NORMAL_LAYER_MAPPING;
-        for (matrix_column, mapping_column) in scan.matrix.iter().zip(layer_mapping) {
-            for (key_pressed, mapping_row) in matrix_column.iter().zip(mapping_column) {
-                if mapping_row == KeyCode::Fn && *key_pressed {
-                    layer_mapping = TRANSPOSED_FN_LAYER_MAPPING;
-                }
-            }
+    /// Removes `key` from the report, the opposite of `press`.
+    pub fn release(&mut self, key: KeyCode) {
+        if let Some(bitmask) = key.modifier_bitmask() {
+            self.modifier &= !bitmask;
+        } else if let Some(slot) = self.keycodes.iter_mut().find(|slot| **slot == key as u8) {
+            *slot = 0;
         }
+    }
+}
+
+/// The state of the consumer and system control usages, which are sent on their own
+/// HID interface because the boot keyboard report can't contain them.
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct MediaReport {
+    pub consumer: u16,
+    pub system: u16,
+}
 
-        // Second scan to generate the correct keycodes given the activated key map
-        for (matrix_column, mapping_column) in scan.matrix.iter().zip(layer_mapping) {
+impl MediaReport {
+    pub fn as_raw_consumer_input(&self) -> [u8; 3] {
+        let [low, high] = self.consumer.to_le_bytes();
+        [CONSUMER_REPORT_ID, low, high]
+    }
+
+    pub fn as_raw_system_input(&self) -> [u8; 3] {
+        let [low, high] = self.system.to_le_bytes();
+        [SYSTEM_REPORT_ID, low, high]
+    }
+}
+
+impl From<&KeyScan<NUM_ROWS, NUM_COLS>> for MediaReport {
+    fn from(scan: &KeyScan<NUM_ROWS, NUM_COLS>) -> Self {
+        let mut report = MediaReport::default();
+
+        // Only one usage of each kind can be reported at a time, the first one found wins.
+        for (matrix_column, mapping_column) in scan.matrix.iter().zip(scan.layer_mapping()) {
             for (key_pressed, mapping_row) in matrix_column.iter().zip(mapping_column) {
-                if *key_pressed {
-                    if let Some(bitmask) = mapping_row.modifier_bitmask() {
-                        modifier |= bitmask;
-                    } else {
-                        push_keycode(mapping_row as u8);
-                    }
+                match (mapping_row, *key_pressed) {
+                    (Action::Consumer(code), true) if report.consumer == 0 => {
+                        report.consumer = code as u16;
+                    },
+                    (Action::System(code), true) if report.system == 0 => {
+                        report.system = code as u16;
+                    },
+                    _ => {},
                 }
             }
         }
 
-        KeyboardReport { modifier, reserved: 0, leds: 0, keycodes }
+        report
     }
 }
 
 // We need the key mappings to be transposed because the key mapping is
-// defined as [[KeyCode; NUM_COLS]; NUM_ROWS] but our scanning logic
-// assumes [[KeyCode; NUM_ROWS]; NUM_COLS].
-pub const TRANSPOSED_NORMAL_LAYER_MAPPING: [[KeyCode; NUM_ROWS]; NUM_COLS] =
-    transpose(NORMAL_LAYER_MAPPING);
-pub const TRANSPOSED_FN_LAYER_MAPPING: [[KeyCode; NUM_ROWS]; NUM_COLS] =
-    transpose(FN_LAYER_MAPPING);
-
+// defined as [[Action; NUM_COLS]; NUM_ROWS] but our scanning logic
+// assumes [[Action; NUM_ROWS]; NUM_COLS].
 pub const fn transpose<const NUM_ROWS: usize, const NUM_COLS: usize>(
-    matrix: [[KeyCode; NUM_COLS]; NUM_ROWS],
-) -> [[KeyCode; NUM_ROWS]; NUM_COLS] {
-    let mut new_matrix: [[KeyCode; NUM_ROWS]; NUM_COLS] = [[KeyCode::Empty; NUM_ROWS]; NUM_COLS];
+    matrix: [[Action; NUM_COLS]; NUM_ROWS],
+) -> [[Action; NUM_ROWS]; NUM_COLS] {
+    let mut new_matrix: [[Action; NUM_ROWS]; NUM_COLS] =
+        [[Action::Key(KeyCode::Empty); NUM_ROWS]; NUM_COLS];
 
     let mut col = 0;
 
@@ -140,3 +317,37 @@ pub const fn transpose<const NUM_ROWS: usize, const NUM_COLS: usize>(
 
     new_matrix
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_mapping::PROFILES;
+
+    fn layer(profile: &'static Profile, keys: &[KeyCode]) -> Layer {
+        let positions: Vec<_> = keys
+            .iter()
+            .map(|key| profile.find(Layer::Normal, |action| action.key() == Some(*key)))
+            .collect();
+
+        KeyScan::with_pressed(profile, &positions).layer()
+    }
+
+    #[test]
+    fn both_thumb_keys_select_the_symbol_layer() {
+        let (macos, windows) = (&PROFILES[0], &PROFILES[1]);
+
+        assert!(layer(macos, &[KeyCode::LeftCmd, KeyCode::RightCmd]) == Layer::Symbol);
+        assert!(layer(macos, &[KeyCode::RightCmd]) == Layer::Normal);
+        assert!(layer(windows, &[KeyCode::LeftAlt, KeyCode::RightAlt]) == Layer::Symbol);
+        assert!(layer(windows, &[KeyCode::LeftAlt]) == Layer::Normal);
+    }
+
+    #[test]
+    fn the_fn_key_selects_the_fn_layer() {
+        let profile = &PROFILES[0];
+        let fn_key =
+            profile.find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(_)));
+
+        assert!(KeyScan::with_pressed(profile, &[fn_key]).layer() == Layer::Fn);
+    }
+}
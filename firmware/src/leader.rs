@@ -0,0 +1,267 @@
+//! A leader key, which listens for a short sequence of keys and then fires the action
+//! configured for that sequence in `LEADER_SEQUENCES`.
+
+use crate::{
+    action::Action,
+    key_codes::KeyCode,
+    key_mapping::LEADER_SEQUENCES,
+    key_scan::{KeyScan, KeyboardReport},
+    macros::Macro,
+    NUM_COLS, NUM_ROWS,
+};
+
+/// The longest key sequence that can follow the leader key.
+pub const MAX_SEQUENCE_LEN: usize = 4;
+
+/// A sequence of keys following the leader key, see `LEADER_SEQUENCES` in `key_mapping.rs`.
+pub struct LeaderSequence {
+    /// The keys to press after the leader key, at most `MAX_SEQUENCE_LEN` of them.
+    pub keys: &'static [KeyCode],
+
+    /// The macro played when the sequence is matched.
+    pub action: Macro,
+}
+
+/// `Leader` captures the keys pressed after the leader key and resolves them against
+/// `LEADER_SEQUENCES`.
+///
+/// A sequence fires as soon as it can't be extended to a longer one, or once no key has
+/// been pressed for `timeout_ticks` (counted from the leader key itself, so a leader key
+/// with nothing after it stops listening too). Sequences that don't match anything are either typed
+/// out as normal keys or dropped, depending on `replay_unmatched`.
+pub struct Leader {
+    listening: bool,
+    sequence: [KeyCode; MAX_SEQUENCE_LEN],
+    sequence_len: usize,
+
+    /// The number of ticks since the leader key or the last key of the sequence was pressed.
+    elapsed_ticks: u16,
+
+    /// The matrix from the previous tick, used to find newly pressed keys.
+    previous_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+
+    /// Keys which were captured by the leader and are hidden until they are released.
+    captured_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+
+    /// The action of the matched sequence, until it is taken by `take_action`.
+    action: Option<Macro>,
+
+    /// The keys of an unmatched sequence, while they are being typed out.
+    replay: [KeyCode; MAX_SEQUENCE_LEN],
+    replay_len: usize,
+
+    /// The number of ticks since the current replay started.
+    output_ticks: u16,
+
+    timeout_ticks: u16,
+    tap_ticks: u16,
+    replay_unmatched: bool,
+}
+
+impl Leader {
+    /// Creates a leader whose replayed keys are each held for `tap_ticks`, which can't be 0.
+    pub fn new(timeout_ticks: u16, tap_ticks: u16, replay_unmatched: bool) -> Self {
+        assert!(tap_ticks > 0, "replayed keys have to be held for at least a tick");
+
+        Self {
+            listening: false,
+            sequence: [KeyCode::Empty; MAX_SEQUENCE_LEN],
+            sequence_len: 0,
+            elapsed_ticks: 0,
+            previous_matrix: [[false; NUM_ROWS]; NUM_COLS],
+            captured_matrix: [[false; NUM_ROWS]; NUM_COLS],
+            action: None,
+            replay: [KeyCode::Empty; MAX_SEQUENCE_LEN],
+            replay_len: 0,
+            output_ticks: 0,
+            timeout_ticks,
+            tap_ticks,
+            replay_unmatched,
+        }
+    }
+
+    /// Removes the leader key and any captured sequence keys from `scan`, and advances
+    /// the leader state by one tick.
+    pub fn tick(&mut self, scan: &mut KeyScan<NUM_ROWS, NUM_COLS>) {
+        let layer_mapping = scan.layer_mapping();
+        self.output_ticks = self.output_ticks.saturating_add(1);
+        self.elapsed_ticks = self.elapsed_ticks.saturating_add(1);
+
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                let action = layer_mapping[col][row];
+                let pressed = scan[col][row];
+                let newly_pressed = pressed && !self.previous_matrix[col][row];
+                self.previous_matrix[col][row] = pressed;
+
+                if matches!(action, Action::Leader) {
+                    if newly_pressed {
+                        self.listening = true;
+                        self.sequence_len = 0;
+                        self.elapsed_ticks = 0;
+                    }
+
+                    scan[col][row] = false;
+                } else if let (Some(key), true) = (action.key(), newly_pressed && self.listening) {
+                    if !key.is_modifier() {
+                        self.captured_matrix[col][row] = true;
+                        self.push(key);
+                    }
+                }
+
+                if !pressed {
+                    self.captured_matrix[col][row] = false;
+                }
+
+                if self.captured_matrix[col][row] {
+                    scan[col][row] = false;
+                }
+            }
+        }
+
+        if self.listening && self.elapsed_ticks >= self.timeout_ticks {
+            self.finish(self.exact_match());
+        }
+    }
+
+    /// Adds the keys currently produced by the leader to `report`.
+    pub fn apply(&self, report: &mut KeyboardReport) {
+        // Each replayed key is pressed for `tap_ticks`, then released for `tap_ticks`.
+        let replay_index = usize::from(self.output_ticks / (2 * self.tap_ticks));
+        if replay_index < self.replay_len
+            && self.output_ticks % (2 * self.tap_ticks) < self.tap_ticks
+        {
+            report.press(self.replay[replay_index]);
+        }
+    }
+
+    /// Returns the macro of a sequence which was just matched, if any.
+    pub fn take_action(&mut self) -> Option<Macro> {
+        self.action.take()
+    }
+
+    fn push(&mut self, key: KeyCode) {
+        self.sequence[self.sequence_len] = key;
+        self.sequence_len += 1;
+        self.elapsed_ticks = 0;
+
+        let sequence = &self.sequence[..self.sequence_len];
+        let can_extend = self.sequence_len < MAX_SEQUENCE_LEN
+            && LEADER_SEQUENCES
+                .iter()
+                .any(|entry| entry.keys.len() > sequence.len() && entry.keys.starts_with(sequence));
+
+        if !can_extend {
+            self.finish(self.exact_match());
+        }
+    }
+
+    fn exact_match(&self) -> Option<&'static LeaderSequence> {
+        LEADER_SEQUENCES.iter().find(|entry| entry.keys == &self.sequence[..self.sequence_len])
+    }
+
+    fn finish(&mut self, matched: Option<&'static LeaderSequence>) {
+        self.listening = false;
+        self.output_ticks = 0;
+        self.action = matched.map(|entry| entry.action);
+        self.replay_len = 0;
+
+        if matched.is_none() && self.replay_unmatched {
+            self.replay = self.sequence;
+            self.replay_len = self.sequence_len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_mapping::PROFILES, key_scan::Layer};
+
+    const TIMEOUT_TICKS: u16 = 10;
+    const TAP_TICKS: u16 = 2;
+
+    fn listening_leader(replay_unmatched: bool) -> Leader {
+        let mut leader = Leader::new(TIMEOUT_TICKS, TAP_TICKS, replay_unmatched);
+        leader.listening = true;
+        leader
+    }
+
+    #[test]
+    fn leader_alone_stops_listening_after_the_timeout() {
+        let a = PROFILES[0].find(Layer::Normal, |action| action.key() == Some(KeyCode::A));
+        let mut leader = listening_leader(true);
+        for _ in 0..TIMEOUT_TICKS {
+            leader.tick(&mut KeyScan::with_pressed(&PROFILES[0], &[]));
+        }
+
+        assert!(!leader.listening);
+        assert!(leader.take_action().is_none());
+        assert_eq!(leader.replay_len, 0);
+
+        // A key pressed later is typed as usual rather than captured.
+        let mut scan = KeyScan::with_pressed(&PROFILES[0], &[a]);
+        leader.tick(&mut scan);
+        assert!(scan[a.0][a.1]);
+        assert_eq!(leader.sequence_len, 0);
+    }
+
+    #[test]
+    fn sequence_fires_once_it_cant_be_extended() {
+        let mut leader = listening_leader(false);
+        leader.push(KeyCode::S);
+
+        assert!(!leader.listening);
+        let expected = LEADER_SEQUENCES.iter().find(|entry| entry.keys == [KeyCode::S]).unwrap();
+        assert_eq!(leader.take_action(), Some(expected.action));
+        assert!(leader.take_action().is_none());
+    }
+
+    #[test]
+    fn prefix_of_a_longer_sequence_keeps_listening() {
+        let mut leader = listening_leader(false);
+        leader.push(KeyCode::G);
+
+        assert!(leader.listening);
+        assert!(leader.take_action().is_none());
+
+        leader.push(KeyCode::S);
+        assert!(!leader.listening);
+        assert!(leader.take_action().is_some());
+    }
+
+    #[test]
+    fn prefix_alone_doesnt_match() {
+        let mut leader = listening_leader(false);
+        leader.push(KeyCode::U);
+
+        assert!(leader.exact_match().is_none());
+    }
+
+    #[test]
+    fn unmatched_sequence_is_dropped_or_replayed() {
+        let mut leader = listening_leader(false);
+        leader.push(KeyCode::X);
+
+        assert!(!leader.listening);
+        assert!(leader.take_action().is_none());
+        assert_eq!(leader.replay_len, 0);
+
+        let mut leader = listening_leader(true);
+        leader.push(KeyCode::G);
+        leader.push(KeyCode::X);
+        assert!(leader.take_action().is_none());
+        assert_eq!(&leader.replay[..leader.replay_len], &[KeyCode::G, KeyCode::X]);
+
+        let mut expected = KeyboardReport::default();
+        expected.press(KeyCode::G);
+        let mut report = KeyboardReport::default();
+        leader.apply(&mut report);
+        assert_eq!(report, expected);
+
+        leader.output_ticks = 3 * TAP_TICKS;
+        let mut report = KeyboardReport::default();
+        leader.apply(&mut report);
+        assert_eq!(report, KeyboardReport::default());
+    }
+}
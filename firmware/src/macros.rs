@@ -0,0 +1,417 @@
+//! Keyboard macros, which play back a sequence of key presses, releases, delays and text.
+
+use crate::{
+    action::Action,
+    host_layout::HostLayout,
+    key_codes::KeyCode,
+    key_scan::{KeyScan, KeyboardReport},
+    typing::Typist,
+    unicode::UnicodeMethod,
+    NUM_COLS, NUM_ROWS, SCAN_LOOP_RATE_MS,
+};
+
+/// A single step of a macro, played by `Action::Macro`.
+#[allow(unused)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MacroStep {
+    /// Press a key and keep it held.
+    Press(KeyCode),
+    /// Release a key held by an earlier `Press`.
+    Release(KeyCode),
+    /// Press and release a key.
+    Tap(KeyCode),
+    /// Wait for a number of milliseconds.
+    Delay(u16),
+    /// Type out a string with the keys of the host's keyboard layout, falling back to the
+    /// current `UnicodeMethod` for characters it doesn't have.
+    Text(&'static str),
+    /// Type out a string using only the current `UnicodeMethod`.
+    Unicode(&'static str),
+    /// Change the `UnicodeMethod` used by `Unicode` steps from now on.
+    SetUnicodeMethod(UnicodeMethod),
+}
+
+/// A macro is a sequence of steps, played back in order. Any keys still held once the last
+/// step has been played are released.
+pub type Macro = &'static [MacroStep];
+
+/// `MacroPlayer` plays back macros, one report at a time.
+///
+/// Every change to the keys held by a macro has to reach the host before the next step is
+/// played, otherwise a press and its release could be merged away. If writing a report
+/// fails (for example with `UsbError::WouldBlock`), the player waits and the same report is
+/// sent again on the next tick.
+pub struct MacroPlayer {
+    steps: Macro,
+    step_index: usize,
+
+    /// Types the string of the current `Text` or `Unicode` step.
+    typist: Option<Typist>,
+
+    /// The keyboard layout the host is set to, used by `Text` steps.
+    host_layout: HostLayout,
+
+    /// How characters the host's keyboard layout doesn't have are typed.
+    unicode_method: UnicodeMethod,
+
+    /// Whether the key of the current `Tap` step (or Backspace) is pressed.
+    tap_pressed: bool,
+
+    /// The number of times left to tap Backspace before the macro starts, see `play_replacing`.
+    backspaces: usize,
+
+    /// Text typed after the last step, see `play_replacing`.
+    suffix: &'static str,
+
+    /// The number of ticks left in the current `Delay` step.
+    delay_ticks: u16,
+
+    /// The keys currently held by the macro.
+    held: KeyboardReport,
+
+    /// The keys currently pressed by a `Text` or `Unicode` step.
+    typed: KeyboardReport,
+
+    /// Whether `held` has changed and not yet been delivered to the host.
+    waiting: bool,
+
+    /// The backspaces, steps and suffix of the macro which was just started, until taken by
+    /// `take_started`.
+    started: Option<(usize, Macro, &'static str)>,
+
+    /// The matrix from the previous tick, used to find newly pressed keys.
+    previous_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+}
+
+impl MacroPlayer {
+    pub fn new(host_layout: HostLayout, unicode_method: UnicodeMethod) -> Self {
+        Self {
+            steps: &[],
+            step_index: 0,
+            typist: None,
+            host_layout,
+            unicode_method,
+            tap_pressed: false,
+            backspaces: 0,
+            suffix: "",
+            delay_ticks: 0,
+            held: KeyboardReport::default(),
+            typed: KeyboardReport::default(),
+            waiting: false,
+            started: None,
+            previous_matrix: [[false; NUM_ROWS]; NUM_COLS],
+        }
+    }
+
+    /// Changes the keyboard layout the host is set to.
+    pub fn set_host_layout(&mut self, host_layout: HostLayout) {
+        self.host_layout = host_layout;
+    }
+
+    /// Changes how characters the host's keyboard layout doesn't have are typed.
+    pub fn set_unicode_method(&mut self, unicode_method: UnicodeMethod) {
+        self.unicode_method = unicode_method;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.backspaces > 0 || self.step_index < self.steps.len() || !self.suffix.is_empty()
+    }
+
+    /// Starts playing `steps`. A macro which is already playing is interrupted, and its held
+    /// keys are released before the new macro starts.
+    pub fn play(&mut self, steps: Macro) {
+        self.stop();
+        self.steps = steps;
+        self.started = Some((0, steps, ""));
+    }
+
+    /// Erases `backspaces` characters by tapping Backspace, then plays `steps` and types
+    /// `suffix`, which is how typed text is replaced.
+    pub fn play_replacing(&mut self, backspaces: usize, steps: Macro, suffix: &'static str) {
+        self.play(steps);
+        self.backspaces = backspaces;
+        self.suffix = suffix;
+        self.started = Some((backspaces, steps, suffix));
+    }
+
+    /// Returns the backspaces, steps and suffix of a macro which was just started, so what
+    /// it types can be followed by a `TypedBuffer`.
+    pub fn take_started(&mut self) -> Option<(usize, Macro, &'static str)> {
+        self.started.take()
+    }
+
+    /// Stops the current macro, releasing any keys it holds.
+    pub fn stop(&mut self) {
+        self.steps = &[];
+        self.step_index = 0;
+        self.typist = None;
+        self.tap_pressed = false;
+        self.backspaces = 0;
+        self.suffix = "";
+        self.delay_ticks = 0;
+        self.release_all();
+    }
+
+    /// Removes the macro keys from `scan`, starts (or stops) macros whose key was just
+    /// pressed, and plays the next step of the current macro.
+    ///
+    /// `report_delivered` is whether the report produced on the previous tick reached the host.
+    pub fn tick(&mut self, scan: &mut KeyScan<NUM_ROWS, NUM_COLS>, report_delivered: bool) {
+        let layer_mapping = scan.layer_mapping();
+
+        // The changes made before this tick have reached the host. Changes made now, such as
+        // releasing the keys of a macro which is interrupted, have to be delivered before the
+        // next step is played.
+        if report_delivered {
+            self.waiting = false;
+        }
+
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                let pressed = scan[col][row];
+                let newly_pressed = pressed && !self.previous_matrix[col][row];
+                self.previous_matrix[col][row] = pressed;
+
+                let Action::Macro(steps) = layer_mapping[col][row] else {
+                    continue;
+                };
+
+                if newly_pressed {
+                    // Pressing the key of a playing macro again cancels it.
+                    if self.is_playing() && core::ptr::eq(self.steps, steps) {
+                        self.stop();
+                    } else {
+                        self.play(steps);
+                    }
+                }
+
+                scan[col][row] = false;
+            }
+        }
+
+        if self.waiting {
+            return;
+        }
+
+        if self.delay_ticks > 0 {
+            self.delay_ticks -= 1;
+            return;
+        }
+
+        self.step();
+    }
+
+    /// Adds the keys currently held by the macro to `report`.
+    pub fn apply(&self, report: &mut KeyboardReport) {
+        // Text is typed with the modifiers it needs, so the ones the user holds mustn't
+        // change it (Shift would type "HELLO" for "hello").
+        if self.typist.is_some() {
+            report.modifier = 0;
+        }
+
+        report.modifier |= self.held.modifier | self.typed.modifier;
+        for key in self.held.keycodes.into_iter().chain(self.typed.keycodes) {
+            if key != 0 && !report.keycodes.contains(&key) {
+                if let Some(slot) = report.keycodes.iter_mut().find(|slot| **slot == 0) {
+                    *slot = key;
+                }
+            }
+        }
+    }
+
+    /// Plays steps until one of them changes the held keys or starts a delay.
+    fn step(&mut self) {
+        if self.backspaces > 0 {
+            if self.tap_pressed {
+                self.held.release(KeyCode::Backspace);
+                self.backspaces -= 1;
+            } else {
+                self.held.press(KeyCode::Backspace);
+            }
+
+            self.tap_pressed = !self.tap_pressed;
+            self.waiting = true;
+            return;
+        }
+
+        while let Some(step) = self.steps.get(self.step_index) {
+            match *step {
+                MacroStep::Press(key) => {
+                    self.held.press(key);
+                    self.step_index += 1;
+                },
+                MacroStep::Release(key) => {
+                    self.held.release(key);
+                    self.step_index += 1;
+                },
+                MacroStep::Tap(key) => {
+                    if self.tap_pressed {
+                        self.held.release(key);
+                        self.step_index += 1;
+                    } else {
+                        self.held.press(key);
+                    }
+
+                    self.tap_pressed = !self.tap_pressed;
+                },
+                MacroStep::Delay(ms) => {
+                    self.delay_ticks = ms / (SCAN_LOOP_RATE_MS as u16);
+                    self.step_index += 1;
+                    return;
+                },
+                MacroStep::Text(text) => {
+                    if !self.type_next(Typist::new(text)) {
+                        self.step_index += 1;
+                        continue;
+                    }
+                },
+                MacroStep::Unicode(text) => {
+                    if !self.type_next(Typist::unicode(text)) {
+                        self.step_index += 1;
+                        continue;
+                    }
+                },
+                MacroStep::SetUnicodeMethod(method) => {
+                    self.unicode_method = method;
+                    self.step_index += 1;
+                    continue;
+                },
+            }
+
+            self.waiting = true;
+            return;
+        }
+
+        if !self.suffix.is_empty() {
+            if self.type_next(Typist::new(self.suffix)) {
+                self.waiting = true;
+                return;
+            }
+
+            self.suffix = "";
+        }
+
+        // The macro has finished, make sure it doesn't leave anything held.
+        if self.held != KeyboardReport::default() {
+            self.release_all();
+        }
+    }
+
+    /// Types the next report of the current `Text` or `Unicode` step, starting it with
+    /// `typist` if needed. Returns `false` once it's done.
+    fn type_next(&mut self, typist: Typist) -> bool {
+        let typist = self.typist.get_or_insert(typist);
+        match typist.next_report(self.host_layout, self.unicode_method) {
+            Some(report) => {
+                self.typed = report;
+                true
+            },
+            None => {
+                self.typist = None;
+                false
+            },
+        }
+    }
+
+    fn release_all(&mut self) {
+        let empty = KeyboardReport::default();
+        self.waiting |= self.held != empty || self.typed != empty;
+        self.held = empty;
+        self.typed = empty;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_mapping::PROFILES, key_scan::Layer};
+
+    fn scan(positions: &[(usize, usize)]) -> KeyScan<NUM_ROWS, NUM_COLS> {
+        KeyScan::with_pressed(&PROFILES[0], positions)
+    }
+
+    fn applied(player: &MacroPlayer) -> KeyboardReport {
+        let mut report = KeyboardReport::default();
+        player.apply(&mut report);
+        report
+    }
+
+    fn pressed(key: KeyCode) -> KeyboardReport {
+        let mut report = KeyboardReport::default();
+        report.press(key);
+        report
+    }
+
+    #[test]
+    fn steps_wait_for_the_previous_report() {
+        let mut player = MacroPlayer::new(HostLayout::Us, UnicodeMethod::MacOs);
+        player.play(&[MacroStep::Tap(KeyCode::A)]);
+
+        player.tick(&mut scan(&[]), true);
+        assert_eq!(applied(&player), pressed(KeyCode::A));
+
+        player.tick(&mut scan(&[]), false);
+        assert_eq!(applied(&player), pressed(KeyCode::A));
+
+        player.tick(&mut scan(&[]), true);
+        assert_eq!(applied(&player), KeyboardReport::default());
+        assert!(!player.is_playing());
+    }
+
+    #[test]
+    fn interrupted_macro_is_released_before_the_next_one_starts() {
+        let fn_key = PROFILES[0]
+            .find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(Layer::Fn)));
+        let macro_key = PROFILES[0].find(Layer::Fn, |action| matches!(action, Action::Macro(_)));
+
+        let mut player = MacroPlayer::new(HostLayout::Us, UnicodeMethod::MacOs);
+        player.play(&[MacroStep::Press(KeyCode::X), MacroStep::Delay(1000)]);
+        player.tick(&mut scan(&[fn_key]), true);
+        assert_eq!(applied(&player), pressed(KeyCode::X));
+
+        // Pressing a macro key interrupts the playing macro, and the release of its keys goes
+        // out on its own.
+        player.tick(&mut scan(&[fn_key, macro_key]), true);
+        assert!(player.is_playing());
+        assert_eq!(applied(&player), KeyboardReport::default());
+
+        player.tick(&mut scan(&[fn_key, macro_key]), false);
+        assert_eq!(applied(&player), KeyboardReport::default());
+
+        player.tick(&mut scan(&[fn_key, macro_key]), true);
+        assert_ne!(applied(&player), KeyboardReport::default());
+    }
+
+    #[test]
+    fn macro_plays_again_after_its_layer_key_was_released_first() {
+        let fn_key = PROFILES[0]
+            .find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(Layer::Fn)));
+        let macro_key = PROFILES[0].find(Layer::Fn, |action| matches!(action, Action::Macro(_)));
+
+        let mut player = MacroPlayer::new(HostLayout::Us, UnicodeMethod::MacOs);
+        player.tick(&mut scan(&[fn_key, macro_key]), true);
+        assert!(player.take_started().is_some());
+
+        // Fn is released before the macro key, which then maps to a plain key.
+        player.tick(&mut scan(&[macro_key]), true);
+        player.tick(&mut scan(&[]), true);
+        while player.is_playing() {
+            player.tick(&mut scan(&[]), true);
+        }
+
+        player.tick(&mut scan(&[fn_key]), true);
+        player.tick(&mut scan(&[fn_key, macro_key]), true);
+        assert!(player.take_started().is_some());
+    }
+
+    #[test]
+    fn held_modifiers_dont_change_typed_text() {
+        let mut player = MacroPlayer::new(HostLayout::Us, UnicodeMethod::MacOs);
+        player.play(&[MacroStep::Text("a")]);
+        player.tick(&mut scan(&[]), true);
+
+        let mut report = pressed(KeyCode::LeftShift);
+        player.apply(&mut report);
+        assert_eq!(report, pressed(KeyCode::A));
+    }
+}
@@ -1,20 +1,46 @@
 // Simple keyboard firmware. Inspired by the RustyKeys project:
 // https://github.com/KOBA789/rusty-keys/blob/main/firmware/keyboard/src/bin/simple.rs
 
-#![no_main]
-#![no_std]
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(not(test), no_std)]
 
+mod action;
+mod auto_shift;
+mod autocorrect;
+mod caps_word;
 mod debounce;
+mod dynamic_macro;
+mod flash;
 mod hid_class;
 mod hid_descriptor;
+mod host_layout;
+mod host_os;
 mod key_codes;
 mod key_mapping;
+mod key_override;
 mod key_scan;
+mod leader;
+mod macros;
+mod one_shot;
+mod profile;
+mod repeat;
+mod rollover;
+mod settings;
+mod space_cadet;
+mod tap_dance;
+mod text_expansion;
+mod typing;
+mod unicode;
 
 use crate::{
     hid_class::HidClass,
-    key_scan::{KeyboardReport, TRANSPOSED_NORMAL_LAYER_MAPPING},
+    hid_descriptor::{KEYBOARD_REPORT_DESCRIPTOR, MEDIA_REPORT_DESCRIPTOR},
+    key_mapping::PROFILES,
+    key_scan::{KeyboardReport, Layer, Leds, MediaReport},
 };
+use auto_shift::{AutoShift, AutoShiftGroups};
+use autocorrect::Autocorrect;
+use caps_word::CapsWord;
 use core::{
     cell::RefCell,
     convert::Infallible,
@@ -25,15 +51,29 @@ use critical_section::Mutex;
 use debounce::Debounce;
 use defmt::{error, info, warn};
 use defmt_rtt as _;
+use dynamic_macro::DynamicMacros;
 use embedded_hal::digital::{InputPin, OutputPin};
+use flash::SettingsRegion;
 use fugit::ExtU32;
+use host_os::{EnumerationTrace, HostOsDetector};
 use key_scan::KeyScan;
+use leader::Leader;
+use macros::MacroPlayer;
+use one_shot::OneShotModifiers;
+#[cfg(not(test))]
 use panic_probe as _;
+use profile::Profiles;
+use repeat::Repeat;
+use rollover::{Rollover, RolloverPolicy};
 use rp2040_hal::{
     pac::{self, interrupt},
     usb::{self, UsbBus},
     Clock, Watchdog,
 };
+use settings::{Settings, SettingsStore};
+use space_cadet::SpaceCadet;
+use tap_dance::TapDancer;
+use text_expansion::{TextExpander, TypedBuffer};
 use usb_device::{bus::UsbBusAllocator, device::UsbDeviceBuilder, prelude::*};
 
 /// The rate of polling of the keyboard itself in firmware.
@@ -43,6 +83,55 @@ const DEBOUNCE_MS: u8 = 6;
 
 const DEBOUNCE_TICKS: u8 = DEBOUNCE_MS / (SCAN_LOOP_RATE_MS as u8);
 
+/// The number of milliseconds a tap-dance key waits for another tap (or a release) before resolving.
+const TAP_DANCE_TIMEOUT_MS: u16 = 200;
+/// The number of milliseconds a key generated by a tap is reported as held.
+const TAP_PRESS_MS: u16 = 10;
+/// The number of milliseconds the leader key waits for the next key of a sequence.
+const LEADER_TIMEOUT_MS: u16 = 1000;
+/// Whether a sequence following the leader key that matches nothing is typed out, or dropped.
+const LEADER_REPLAY_UNMATCHED: bool = true;
+/// The number of milliseconds a tapped one-shot modifier waits for the next key.
+const ONE_SHOT_TIMEOUT_MS: u16 = 1000;
+/// The number of milliseconds without a key press after which Caps Word turns itself off.
+const CAPS_WORD_TIMEOUT_MS: u16 = 5000;
+/// Whether auto-shift is enabled, until it's toggled with the `AutoShiftToggle` key. The
+/// toggle is kept across power cycles.
+const AUTO_SHIFT_ENABLED: bool = false;
+/// The groups of keys which are shifted when held down.
+const AUTO_SHIFT_GROUPS: AutoShiftGroups =
+    AutoShiftGroups { letters: true, numbers: true, symbols: true };
+/// The number of milliseconds a key has to be held down before auto-shift sends it shifted.
+const AUTO_SHIFT_TIMEOUT_MS: u16 = 175;
+/// The number of milliseconds a Space Cadet key can be held and still count as a tap.
+const SPACE_CADET_TIMEOUT_MS: u16 = 200;
+/// Whether typos from `autocorrect.txt` are corrected, until it's toggled with the
+/// `AutocorrectToggle` key. The toggle is kept across power cycles.
+const AUTOCORRECT_ENABLED: bool = true;
+/// Which keys to report when more than six keys are held at once.
+const ROLLOVER_POLICY: RolloverPolicy = RolloverPolicy::KeepNewest;
+/// The index of the profile in `PROFILES` which is active on power-on, until the host's OS
+/// is detected. Others can be selected with the `SelectProfile` keys, and the last profile
+/// selected is active on power-on instead. Once a profile has been selected with a key, the
+/// host's OS no longer selects one, even after a power cycle.
+const DEFAULT_PROFILE: usize = 0;
+/// The number of milliseconds without control requests from the host after which enumeration
+/// is considered done, and the host's OS is detected.
+const HOST_OS_DETECTION_DELAY_MS: u16 = 500;
+/// The number of milliseconds changed settings have to stay the same before they're saved to
+/// flash. Saving stalls the keyboard (and USB) for a moment, and wears the flash.
+const SETTINGS_SAVE_DELAY_MS: u16 = 3000;
+
+const TAP_DANCE_TIMEOUT_TICKS: u16 = TAP_DANCE_TIMEOUT_MS / (SCAN_LOOP_RATE_MS as u16);
+const TAP_PRESS_TICKS: u16 = TAP_PRESS_MS / (SCAN_LOOP_RATE_MS as u16);
+const LEADER_TIMEOUT_TICKS: u16 = LEADER_TIMEOUT_MS / (SCAN_LOOP_RATE_MS as u16);
+const ONE_SHOT_TIMEOUT_TICKS: u16 = ONE_SHOT_TIMEOUT_MS / (SCAN_LOOP_RATE_MS as u16);
+const CAPS_WORD_TIMEOUT_TICKS: u16 = CAPS_WORD_TIMEOUT_MS / (SCAN_LOOP_RATE_MS as u16);
+const AUTO_SHIFT_TIMEOUT_TICKS: u16 = AUTO_SHIFT_TIMEOUT_MS / (SCAN_LOOP_RATE_MS as u16);
+const SPACE_CADET_TIMEOUT_TICKS: u16 = SPACE_CADET_TIMEOUT_MS / (SCAN_LOOP_RATE_MS as u16);
+const HOST_OS_DETECTION_DELAY_TICKS: u16 = HOST_OS_DETECTION_DELAY_MS / (SCAN_LOOP_RATE_MS as u16);
+const SETTINGS_SAVE_DELAY_TICKS: u16 = SETTINGS_SAVE_DELAY_MS / (SCAN_LOOP_RATE_MS as u16);
+
 /// The linker will place this boot block at the start of our program image. We
 /// need this to help the ROM bootloader get our code up and running.
 #[link_section = ".boot2"]
@@ -64,14 +153,20 @@ static mut USB_BUS: Option<UsbBusAllocator<usb::UsbBus>> = None;
 static USB_HID_CLASS: Mutex<RefCell<Option<HidClass<usb::UsbBus>>>> =
     Mutex::new(RefCell::new(None));
 
+/// The USB Human Interface Device Driver for consumer and system control usages
+/// (shared with the interrupt).
+static USB_MEDIA_HID_CLASS: Mutex<RefCell<Option<HidClass<usb::UsbBus>>>> =
+    Mutex::new(RefCell::new(None));
+
 static ATTEMPT_REMOTE_WAKEUP: AtomicBool = AtomicBool::new(false);
 
+#[cfg(not(test))]
 #[defmt::panic_handler]
 fn panic() -> ! {
     cortex_m::asm::udf()
 }
 
-#[cortex_m_rt::entry]
+#[cfg_attr(not(test), cortex_m_rt::entry)]
 fn main() -> ! {
     info!("Start of main()");
     let mut pac = pac::Peripherals::take().unwrap();
@@ -129,17 +224,41 @@ fn main() -> ! {
     let timer = rp2040_hal::Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
 
     let mut modifier_mask = [[false; NUM_ROWS]; NUM_COLS];
-    for (col, mapping_col) in modifier_mask.iter_mut().zip(TRANSPOSED_NORMAL_LAYER_MAPPING) {
-        for (key, mapping_key) in col.iter_mut().zip(mapping_col) {
-            *key = mapping_key.is_modifier();
+    for profile in PROFILES {
+        for (col, mapping_col) in modifier_mask.iter_mut().zip(profile.layer_mapping(Layer::Normal))
+        {
+            for (key, mapping_key) in col.iter_mut().zip(mapping_col) {
+                *key |= mapping_key.is_modifier();
+            }
         }
     }
 
     // Create a global debounce state to prevent unintended rapid key double-presses.
     let mut debounce: Debounce<NUM_ROWS, NUM_COLS> = Debounce::new(DEBOUNCE_TICKS, modifier_mask);
 
-    // Do an initial scan of the keys so that we immediately have something to report to the host when asked.
-    let scan = KeyScan::scan(&mut rows, &mut cols, &mut delay, &mut debounce);
+    // Load the settings saved in flash, such as the last profile used.
+    let mut settings_store = SettingsStore::load(
+        SettingsRegion,
+        Settings {
+            profile: DEFAULT_PROFILE,
+            profile_selected_manually: false,
+            auto_shift_enabled: AUTO_SHIFT_ENABLED,
+            autocorrect_enabled: AUTOCORRECT_ENABLED,
+        },
+        SETTINGS_SAVE_DELAY_TICKS,
+    );
+    let settings = settings_store.settings();
+    let mut profiles = Profiles::new(settings.profile, settings.profile_selected_manually);
+
+    // Do an initial scan of the keys to find any held during power-on.
+    let scan = KeyScan::scan(
+        &mut rows,
+        &mut cols,
+        &mut delay,
+        &mut debounce,
+        Leds::default(),
+        profiles.active(),
+    );
 
     // If the Escape key is pressed during power-on, we should go into bootloader mode.
     if scan[0][0] {
@@ -171,7 +290,8 @@ fn main() -> ! {
         USB_BUS.as_ref().unwrap()
     };
 
-    let hid_class = HidClass::new(bus_allocator_ref);
+    let hid_class = HidClass::new(bus_allocator_ref, KEYBOARD_REPORT_DESCRIPTOR, true);
+    let media_hid_class = HidClass::new(bus_allocator_ref, MEDIA_REPORT_DESCRIPTOR, false);
 
     // https://github.com/obdev/v-usb/blob/7a28fdc685952412dad2b8842429127bc1cf9fa7/usbdrv/USB-IDs-for-free.txt#L128
     let keyboard_usb_device = UsbDeviceBuilder::new(bus_allocator_ref, UsbVidPid(0x16c0, 0x27db))
@@ -184,6 +304,7 @@ fn main() -> ! {
         // Note (safety): This is safe as interrupts haven't been started yet
         critical_section::with(|cs| {
             USB_HID_CLASS.replace(cs, Some(hid_class));
+            USB_MEDIA_HID_CLASS.replace(cs, Some(media_hid_class));
         });
 
         USB_DEVICE = Some(keyboard_usb_device);
@@ -197,45 +318,179 @@ fn main() -> ! {
     let mut tick_count_down = timer.count_down();
     tick_count_down.start(1.millis());
 
-    let mut last_report: KeyboardReport = scan.into();
+    let mut last_report = KeyboardReport::default();
+    let mut last_media_report = MediaReport::default();
+
+    let mut leader = Leader::new(LEADER_TIMEOUT_TICKS, TAP_PRESS_TICKS, LEADER_REPLAY_UNMATCHED);
+    let mut tap_dancer = TapDancer::new(TAP_DANCE_TIMEOUT_TICKS, TAP_PRESS_TICKS);
+    let mut macro_player =
+        MacroPlayer::new(profiles.active().host_layout, profiles.active().unicode_method);
+    let mut dynamic_macros = DynamicMacros::new();
+    let mut space_cadet = SpaceCadet::new(SPACE_CADET_TIMEOUT_TICKS);
+    let mut one_shot_modifiers = OneShotModifiers::new(ONE_SHOT_TIMEOUT_TICKS);
+    let mut caps_word = CapsWord::new(CAPS_WORD_TIMEOUT_TICKS);
+    let mut auto_shift = AutoShift::new(
+        settings.auto_shift_enabled,
+        AUTO_SHIFT_GROUPS,
+        AUTO_SHIFT_TIMEOUT_TICKS,
+        TAP_PRESS_TICKS,
+    );
+    let mut rollover = Rollover::new(ROLLOVER_POLICY);
+    let mut repeat = Repeat::new();
+    let mut typed = TypedBuffer::new();
+    let mut text_expander = TextExpander::new();
+    let mut autocorrect = Autocorrect::new(settings.autocorrect_enabled);
+    let mut host_os_detector = HostOsDetector::new(HOST_OS_DETECTION_DELAY_TICKS);
+    let mut report_delivered = true;
 
     loop {
         if tick_count_down.wait().is_ok() {
-            let scan = KeyScan::scan(&mut rows, &mut cols, &mut delay, &mut debounce);
-            let report: KeyboardReport = scan.into();
+            let leds = read_leds(&USB_HID_CLASS);
+            let mut scan = KeyScan::scan(
+                &mut rows,
+                &mut cols,
+                &mut delay,
+                &mut debounce,
+                leds,
+                profiles.active(),
+            );
+            dynamic_macros.tick(&mut scan, report_delivered);
+            host_os_detector.tick(&read_enumeration_trace(&USB_HID_CLASS));
+            profiles.tick(&mut scan);
+            leader.tick(&mut scan);
+            tap_dancer.tick(&mut scan);
+            macro_player.tick(&mut scan, report_delivered);
+            space_cadet.tick(&mut scan);
+            auto_shift.tick(&mut scan, one_shot_modifiers.modifiers());
+            caps_word.tick(&mut scan);
+            one_shot_modifiers.tick(&scan);
+            autocorrect.tick(&mut scan);
+            repeat.tick(&mut scan);
+            rollover.tick(&scan);
+
+            if let Some(host_os) = host_os_detector.take_action() {
+                info!("Detected the host OS as {}", host_os);
+                profiles.select_for_host_os(host_os);
+            }
+
+            if let Some(profile) = profiles.take_action() {
+                info!("Switched to the {} profile", profile.name);
+                macro_player.set_host_layout(profile.host_layout);
+                macro_player.set_unicode_method(profile.unicode_method);
+            }
+
+            if let Some(action) = leader.take_action().or_else(|| space_cadet.take_action()) {
+                macro_player.play(action);
+            }
+
+            if let Some((backspaces, expansion)) = text_expander.take_action() {
+                macro_player.play_replacing(backspaces, expansion, "");
+            }
+
+            if let Some((backspaces, correction, suffix)) = autocorrect.take_action() {
+                macro_player.play_replacing(backspaces, correction, suffix);
+            }
+
+            if let Some((backspaces, steps, suffix)) = macro_player.take_started() {
+                typed.replace(backspaces, steps, suffix);
+            }
+
+            let mut report = rollover.report(&scan);
+            leader.apply(&mut report);
+            tap_dancer.apply(&mut report);
+            one_shot_modifiers.apply(&mut report);
+            caps_word.apply(&mut report);
+            auto_shift.apply(&mut report);
+            key_override::apply(&mut report, scan.layer());
+            repeat.apply(&mut report);
+            typed.update(&mut report);
+            text_expander.apply(&mut report, &mut typed);
+            autocorrect.apply(&mut report, &mut typed);
+            host_layout::translate(&mut report, profiles.active().host_layout);
+            // Macros type text in the host's layout already, so they skip the translation.
+            macro_player.apply(&mut report);
 
             if report != last_report {
-                critical_section::with(|cs| {
-                    let mut hid_class = USB_HID_CLASS.borrow_ref_mut(cs);
-                    let hid_class = hid_class.as_mut().unwrap();
-
-                    if let Err(err) = hid_class.write_raw_report(&report.as_raw_input()) {
-                        match err {
-                            UsbError::WouldBlock => warn!("UsbError::WouldBlock"),
-                            UsbError::ParseError => error!("UsbError::ParseError"),
-                            UsbError::BufferOverflow => error!("UsbError::BufferOverflow"),
-                            UsbError::EndpointOverflow => error!("UsbError::EndpointOverflow"),
-                            UsbError::EndpointMemoryOverflow => {
-                                error!("UsbError::EndpointMemoryOverflow")
-                            },
-                            UsbError::InvalidEndpoint => error!("UsbError::InvalidEndpoint"),
-                            UsbError::Unsupported => error!("UsbError::Unsupported"),
-                            UsbError::InvalidState => error!("UsbError::InvalidState"),
-                        }
-                    } else {
-                        // Only assign to last_report if it was successfully reported.
-                        last_report = report;
-                    }
-                });
+                // Only assign to last_report if it was successfully reported.
+                if write_report(&USB_HID_CLASS, &report.as_raw_input()) {
+                    last_report = report;
+                }
 
                 // If the input report has changed, we should attempt a remote wakeup
                 // if the device is suspended.
                 ATTEMPT_REMOTE_WAKEUP.store(true, Ordering::Relaxed);
             }
+
+            let media_report = MediaReport::from(&scan);
+
+            if media_report.consumer != last_media_report.consumer
+                && write_report(&USB_MEDIA_HID_CLASS, &media_report.as_raw_consumer_input())
+            {
+                last_media_report.consumer = media_report.consumer;
+            }
+
+            if media_report.system != last_media_report.system
+                && write_report(&USB_MEDIA_HID_CLASS, &media_report.as_raw_system_input())
+            {
+                last_media_report.system = media_report.system;
+            }
+
+            report_delivered = report == last_report;
+
+            settings_store.tick(Settings {
+                profile: profiles.active_index(),
+                profile_selected_manually: profiles.selected_manually(),
+                auto_shift_enabled: auto_shift.is_enabled(),
+                autocorrect_enabled: autocorrect.is_enabled(),
+            });
         }
     }
 }
 
+/// Returns the keyboard LEDs as last set by the host.
+fn read_leds(hid_class: &Mutex<RefCell<Option<HidClass<usb::UsbBus>>>>) -> Leds {
+    critical_section::with(|cs| {
+        let hid_class = hid_class.borrow_ref(cs);
+        Leds(hid_class.as_ref().map_or(0, HidClass::leds))
+    })
+}
+
+fn read_enumeration_trace(
+    hid_class: &Mutex<RefCell<Option<HidClass<usb::UsbBus>>>>,
+) -> EnumerationTrace {
+    critical_section::with(|cs| {
+        let hid_class = hid_class.borrow_ref(cs);
+        hid_class.as_ref().map_or(EnumerationTrace::new(), HidClass::enumeration_trace)
+    })
+}
+
+/// Writes a raw input report to `hid_class`, returning whether it was successfully written.
+fn write_report(hid_class: &Mutex<RefCell<Option<HidClass<usb::UsbBus>>>>, report: &[u8]) -> bool {
+    critical_section::with(|cs| {
+        let mut hid_class = hid_class.borrow_ref_mut(cs);
+        let hid_class = hid_class.as_mut().unwrap();
+
+        if let Err(err) = hid_class.write_raw_report(report) {
+            match err {
+                UsbError::WouldBlock => warn!("UsbError::WouldBlock"),
+                UsbError::ParseError => error!("UsbError::ParseError"),
+                UsbError::BufferOverflow => error!("UsbError::BufferOverflow"),
+                UsbError::EndpointOverflow => error!("UsbError::EndpointOverflow"),
+                UsbError::EndpointMemoryOverflow => {
+                    error!("UsbError::EndpointMemoryOverflow")
+                },
+                UsbError::InvalidEndpoint => error!("UsbError::InvalidEndpoint"),
+                UsbError::Unsupported => error!("UsbError::Unsupported"),
+                UsbError::InvalidState => error!("UsbError::InvalidState"),
+            }
+
+            false
+        } else {
+            true
+        }
+    })
+}
+
 /// Handle USB interrupts
 #[allow(non_snake_case)]
 #[interrupt]
@@ -247,8 +502,10 @@ unsafe fn USBCTRL_IRQ() {
     critical_section::with(|cs| {
         let mut hid_class = USB_HID_CLASS.borrow_ref_mut(cs);
         let hid_class = hid_class.as_mut().unwrap();
+        let mut media_hid_class = USB_MEDIA_HID_CLASS.borrow_ref_mut(cs);
+        let media_hid_class = media_hid_class.as_mut().unwrap();
 
-        usb_dev.poll(&mut [hid_class]);
+        usb_dev.poll(&mut [hid_class, media_hid_class]);
 
         if usb_dev.state() == UsbDeviceState::Suspend
             && usb_dev.remote_wakeup_enabled()
@@ -0,0 +1,259 @@
+//! One-shot (sticky) modifiers. Tapping a one-shot modifier applies it to the next
+//! non-modifier key, tapping it twice locks it until it is tapped again.
+
+use crate::{
+    action::Action,
+    key_codes::KeyCode,
+    key_scan::{KeyScan, KeyboardReport},
+    NUM_COLS, NUM_ROWS,
+};
+
+/// `OneShotModifiers` tracks the modifiers of the `Action::OneShot` keys which have been
+/// tapped.
+///
+/// A tapped modifier is "armed" until the next non-modifier key is pressed, and is then
+/// reported for as long as that key is held. An armed modifier is cancelled if no key is
+/// pressed within `timeout_ticks`, and tapping it again within that time locks it instead.
+/// Holding a one-shot modifier while pressing another key behaves like a normal modifier.
+pub struct OneShotModifiers {
+    /// The modifier bitmask of the one-shot keys held on the previous tick.
+    held: u8,
+
+    /// The modifier bitmask of the held one-shot keys which another key was pressed with,
+    /// making them regular modifier presses rather than taps.
+    interrupted: u8,
+
+    /// The modifier bitmask waiting for the next key.
+    armed: u8,
+
+    /// The modifier bitmask applied to the key at `consumer`.
+    active: u8,
+
+    /// The modifier bitmask which stays applied until its modifiers are tapped again.
+    locked: u8,
+
+    /// The position of the key which consumed the armed modifiers.
+    consumer: Option<(usize, usize)>,
+
+    /// The number of ticks since modifiers were last armed.
+    armed_ticks: u16,
+
+    /// The matrix from the previous tick, used to find newly pressed keys.
+    previous_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+
+    timeout_ticks: u16,
+}
+
+impl OneShotModifiers {
+    pub fn new(timeout_ticks: u16) -> Self {
+        Self {
+            held: 0,
+            interrupted: 0,
+            armed: 0,
+            active: 0,
+            locked: 0,
+            consumer: None,
+            armed_ticks: 0,
+            previous_matrix: [[false; NUM_ROWS]; NUM_COLS],
+            timeout_ticks,
+        }
+    }
+
+    /// Advances the one-shot modifiers by one tick, given the keys pressed in `scan`.
+    pub fn tick(&mut self, scan: &KeyScan<NUM_ROWS, NUM_COLS>) {
+        let layer_mapping = scan.layer_mapping();
+        self.armed_ticks = self.armed_ticks.saturating_add(1);
+
+        // Find the held one-shot keys, and the first non-modifier key which was pressed on
+        // this tick. Keys which do nothing on the active layer, like the Fn key on the Fn
+        // layer, don't count.
+        let mut held = 0;
+        let mut newly_pressed = None;
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                let action = layer_mapping[col][row];
+                if !scan[col][row] {
+                    continue;
+                }
+
+                if let Action::OneShot(key) = action {
+                    held |= key.modifier_bitmask().unwrap_or(0);
+                } else if !self.previous_matrix[col][row]
+                    && !action.is_modifier()
+                    && !matches!(action, Action::Key(KeyCode::Empty))
+                    && newly_pressed.is_none()
+                {
+                    newly_pressed = Some((col, row));
+                }
+            }
+        }
+
+        if let Some((col, row)) = self.consumer {
+            if !scan[col][row] {
+                self.active = 0;
+                self.consumer = None;
+            }
+        }
+
+        let newly_held = held & !self.held;
+        self.interrupted &= !newly_held;
+        if newly_pressed.is_some() {
+            self.interrupted |= held & !newly_held;
+        }
+
+        // Tapping a locked modifier unlocks it, tapping an armed one locks it, and tapping
+        // any other modifier arms it.
+        let tapped = self.held & !held & !self.interrupted;
+        if tapped != 0 {
+            let unlocked = tapped & self.locked;
+            self.locked &= !unlocked;
+
+            let mut locked = 0;
+            if self.armed_ticks < self.timeout_ticks {
+                locked = tapped & !unlocked & self.armed;
+                self.armed &= !locked;
+                self.locked |= locked;
+            }
+
+            let armed = tapped & !unlocked & !locked;
+            if armed != 0 {
+                self.armed |= armed;
+                self.armed_ticks = 0;
+            }
+        }
+
+        self.held = held;
+
+        if self.armed != 0 {
+            if let Some(consumer) = newly_pressed {
+                self.active |= self.armed;
+                self.armed = 0;
+                self.consumer = Some(consumer);
+            } else if self.armed_ticks >= self.timeout_ticks {
+                self.armed = 0;
+            }
+        }
+
+        self.previous_matrix = **scan;
+    }
+
+    /// The bitmask of the one-shot modifiers which are armed, active or locked.
+    pub fn modifiers(&self) -> u8 {
+        self.armed | self.active | self.locked
+    }
+
+    /// Adds the active and locked one-shot modifiers to `report`.
+    pub fn apply(&self, report: &mut KeyboardReport) {
+        report.modifier |= self.active | self.locked;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_mapping::PROFILES, key_scan::Layer};
+
+    const TIMEOUT_TICKS: u16 = 10;
+
+    /// Runs a tick with the keys at `positions` pressed, returning the report.
+    fn run(one_shot: &mut OneShotModifiers, positions: &[(usize, usize)]) -> KeyboardReport {
+        let scan = KeyScan::with_pressed(&PROFILES[0], positions);
+        one_shot.tick(&scan);
+
+        let mut report = KeyboardReport::default();
+        let layer_mapping = scan.layer_mapping();
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                if scan[col][row] {
+                    report.press_action(layer_mapping[col][row]);
+                }
+            }
+        }
+
+        one_shot.apply(&mut report);
+        report
+    }
+
+    /// Taps the one-shot Shift on the Fn layer.
+    fn tap_shift(one_shot: &mut OneShotModifiers) {
+        let fn_key = PROFILES[0]
+            .find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(Layer::Fn)));
+        let shift = PROFILES[0]
+            .find(Layer::Fn, |action| matches!(action, Action::OneShot(KeyCode::LeftShift)));
+        run(one_shot, &[fn_key, shift]);
+        run(one_shot, &[fn_key]);
+        run(one_shot, &[]);
+    }
+
+    fn key(key: KeyCode) -> (usize, usize) {
+        PROFILES[0].find(Layer::Normal, |action| action.key() == Some(key))
+    }
+
+    fn report(keys: &[KeyCode]) -> KeyboardReport {
+        let mut report = KeyboardReport::default();
+        for key in keys {
+            report.press(*key);
+        }
+
+        report
+    }
+
+    #[test]
+    fn tapped_modifier_applies_to_the_next_key() {
+        let mut one_shot = OneShotModifiers::new(TIMEOUT_TICKS);
+        tap_shift(&mut one_shot);
+
+        assert_eq!(
+            run(&mut one_shot, &[key(KeyCode::A)]),
+            report(&[KeyCode::LeftShift, KeyCode::A])
+        );
+        assert_eq!(
+            run(&mut one_shot, &[key(KeyCode::A)]),
+            report(&[KeyCode::LeftShift, KeyCode::A])
+        );
+        assert_eq!(run(&mut one_shot, &[]), report(&[]));
+        assert_eq!(run(&mut one_shot, &[key(KeyCode::B)]), report(&[KeyCode::B]));
+    }
+
+    #[test]
+    fn plain_modifiers_are_not_one_shot() {
+        let mut one_shot = OneShotModifiers::new(TIMEOUT_TICKS);
+        run(&mut one_shot, &[key(KeyCode::LeftShift)]);
+        run(&mut one_shot, &[]);
+
+        assert_eq!(run(&mut one_shot, &[key(KeyCode::A)]), report(&[KeyCode::A]));
+    }
+
+    #[test]
+    fn double_tapped_modifier_locks_until_tapped_again() {
+        let mut one_shot = OneShotModifiers::new(TIMEOUT_TICKS);
+        tap_shift(&mut one_shot);
+        tap_shift(&mut one_shot);
+
+        assert_eq!(
+            run(&mut one_shot, &[key(KeyCode::A)]),
+            report(&[KeyCode::LeftShift, KeyCode::A])
+        );
+        run(&mut one_shot, &[]);
+        assert_eq!(
+            run(&mut one_shot, &[key(KeyCode::B)]),
+            report(&[KeyCode::LeftShift, KeyCode::B])
+        );
+        run(&mut one_shot, &[]);
+
+        tap_shift(&mut one_shot);
+        assert_eq!(run(&mut one_shot, &[key(KeyCode::A)]), report(&[KeyCode::A]));
+    }
+
+    #[test]
+    fn armed_modifier_is_cancelled_after_the_timeout() {
+        let mut one_shot = OneShotModifiers::new(TIMEOUT_TICKS);
+        tap_shift(&mut one_shot);
+        for _ in 0..TIMEOUT_TICKS {
+            run(&mut one_shot, &[]);
+        }
+
+        assert_eq!(one_shot.modifiers(), 0);
+        assert_eq!(run(&mut one_shot, &[key(KeyCode::A)]), report(&[KeyCode::A]));
+    }
+}
@@ -0,0 +1,233 @@
+//! Profiles, which are complete keymaps together with the settings for a host, and can be
+//! switched between at runtime.
+
+use crate::{
+    action::Action,
+    host_layout::HostLayout,
+    host_os::HostOs,
+    key_mapping::PROFILES,
+    key_scan::{transpose, KeyScan, Layer},
+    space_cadet::{SpaceCadetKey, MAX_SPACE_CADET_KEYS},
+    unicode::UnicodeMethod,
+    NUM_COLS, NUM_ROWS,
+};
+
+/// A keymap and its settings, see `PROFILES` in `key_mapping.rs`.
+pub struct Profile {
+    /// The name shown in the logs when the profile is selected.
+    pub name: &'static str,
+
+    /// The OS this profile is selected for when the host is detected to run it.
+    host_os: HostOs,
+
+    /// The layer mappings, transposed to match the scan matrix.
+    normal_layer: [[Action; NUM_ROWS]; NUM_COLS],
+    fn_layer: [[Action; NUM_ROWS]; NUM_COLS],
+    symbol_layer: [[Action; NUM_ROWS]; NUM_COLS],
+
+    /// The keyboard layout the host is set to, so the legends on the keycaps and macro text
+    /// are typed correctly.
+    pub host_layout: HostLayout,
+
+    /// How characters missing from the host's keyboard layout are typed, until changed with a
+    /// `SetUnicodeMethod` macro step.
+    pub unicode_method: UnicodeMethod,
+
+    /// The modifiers which type something else when tapped on their own, as they sit in
+    /// different places in each keymap.
+    pub space_cadet_keys: &'static [SpaceCadetKey],
+}
+
+impl Profile {
+    // Profiles are written out in a table in `key_mapping.rs`, one argument per setting.
+    #[allow(clippy::too_many_arguments)]
+    pub const fn new(
+        name: &'static str,
+        host_os: HostOs,
+        normal_layer: [[Action; NUM_COLS]; NUM_ROWS],
+        fn_layer: [[Action; NUM_COLS]; NUM_ROWS],
+        symbol_layer: [[Action; NUM_COLS]; NUM_ROWS],
+        host_layout: HostLayout,
+        unicode_method: UnicodeMethod,
+        space_cadet_keys: &'static [SpaceCadetKey],
+    ) -> Self {
+        assert!(space_cadet_keys.len() <= MAX_SPACE_CADET_KEYS);
+
+        Self {
+            name,
+            host_os,
+            normal_layer: transpose(normal_layer),
+            fn_layer: transpose(fn_layer),
+            symbol_layer: transpose(symbol_layer),
+            host_layout,
+            unicode_method,
+            space_cadet_keys,
+        }
+    }
+
+    /// Returns the transposed mapping of `layer`.
+    pub fn layer_mapping(&self, layer: Layer) -> [[Action; NUM_ROWS]; NUM_COLS] {
+        match layer {
+            Layer::Normal => self.normal_layer,
+            Layer::Fn => self.fn_layer,
+            Layer::Symbol => self.symbol_layer,
+        }
+    }
+
+    /// Returns the (column, row) position of the first key in `layer` matching `predicate`,
+    /// for tests.
+    #[cfg(test)]
+    pub fn find(&self, layer: Layer, predicate: impl Fn(Action) -> bool) -> (usize, usize) {
+        let mapping = self.layer_mapping(layer);
+        (0..NUM_COLS)
+            .flat_map(|col| (0..NUM_ROWS).map(move |row| (col, row)))
+            .find(|&(col, row)| predicate(mapping[col][row]))
+            .expect("no key in the layer matches")
+    }
+}
+
+/// `Profiles` keeps track of the active profile, and switches to another one when its
+/// `SelectProfile` key is pressed, or when the host is detected to run another OS.
+///
+/// Selecting a profile with a key overrides the detection, and is kept across power cycles
+/// with the settings.
+pub struct Profiles {
+    /// The index of the active profile in `PROFILES`.
+    active: usize,
+
+    /// Whether the active profile was selected with a `SelectProfile` key.
+    selected_manually: bool,
+
+    /// Whether a different profile was just selected, until taken by `take_action`.
+    switched: bool,
+
+    /// The matrix from the previous tick, used to find newly pressed keys.
+    previous_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+}
+
+impl Profiles {
+    pub fn new(active: usize, selected_manually: bool) -> Self {
+        Self {
+            active,
+            selected_manually,
+            switched: false,
+            previous_matrix: [[false; NUM_ROWS]; NUM_COLS],
+        }
+    }
+
+    pub fn active(&self) -> &'static Profile {
+        &PROFILES[self.active]
+    }
+
+    /// The index of the active profile in `PROFILES`.
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    /// Whether the active profile was selected with a `SelectProfile` key.
+    pub fn selected_manually(&self) -> bool {
+        self.selected_manually
+    }
+
+    /// Removes the `SelectProfile` keys from `scan`, switching profiles when one is pressed.
+    /// The new profile's keymap is used from the next scan on.
+    pub fn tick(&mut self, scan: &mut KeyScan<NUM_ROWS, NUM_COLS>) {
+        let layer_mapping = scan.layer_mapping();
+
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                let pressed = scan[col][row];
+                let newly_pressed = pressed && !self.previous_matrix[col][row];
+                self.previous_matrix[col][row] = pressed;
+
+                if let Action::SelectProfile(index) = layer_mapping[col][row] {
+                    if newly_pressed && index < PROFILES.len() {
+                        self.selected_manually = true;
+                        self.switch_to(index);
+                    }
+
+                    scan[col][row] = false;
+                }
+            }
+        }
+    }
+
+    /// Switches to the first profile for `host_os`, unless a profile was selected with a key.
+    pub fn select_for_host_os(&mut self, host_os: HostOs) {
+        if self.selected_manually {
+            return;
+        }
+
+        if let Some(index) = PROFILES.iter().position(|profile| profile.host_os == host_os) {
+            self.switch_to(index);
+        }
+    }
+
+    fn switch_to(&mut self, index: usize) {
+        if index != self.active {
+            self.active = index;
+            self.switched = true;
+        }
+    }
+
+    /// Returns the newly selected profile, if the profile was just switched.
+    pub fn take_action(&mut self) -> Option<&'static Profile> {
+        core::mem::take(&mut self.switched).then(|| self.active())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Presses Fn and the `SelectProfile` key for the profile at `index`.
+    fn select(profiles: &mut Profiles, index: usize) {
+        let profile = profiles.active();
+        let fn_key = profile
+            .find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(Layer::Fn)));
+        let select_key = profile.find(
+            Layer::Fn,
+            |action| matches!(action, Action::SelectProfile(selected) if selected == index),
+        );
+
+        let mut scan = KeyScan::with_pressed(profile, &[fn_key, select_key]);
+        profiles.tick(&mut scan);
+        assert!(!scan[select_key.0][select_key.1]);
+        profiles.tick(&mut KeyScan::with_pressed(profile, &[]));
+    }
+
+    #[test]
+    fn profile_keys_switch_profiles() {
+        let mut profiles = Profiles::new(0, false);
+        select(&mut profiles, 2);
+
+        assert_eq!(profiles.active_index(), 2);
+        assert!(profiles.selected_manually());
+        assert_eq!(profiles.take_action().map(|profile| profile.name), Some(PROFILES[2].name));
+        assert!(profiles.take_action().is_none());
+    }
+
+    #[test]
+    fn the_hosts_os_selects_its_profile() {
+        let mut profiles = Profiles::new(0, false);
+        profiles.select_for_host_os(HostOs::Windows);
+
+        assert_eq!(profiles.active_index(), 1);
+        assert!(!profiles.selected_manually());
+        assert!(profiles.take_action().is_some());
+    }
+
+    #[test]
+    fn a_profile_selected_with_a_key_wins_over_the_hosts_os() {
+        let mut profiles = Profiles::new(0, false);
+        select(&mut profiles, 2);
+        profiles.select_for_host_os(HostOs::Windows);
+        assert_eq!(profiles.active_index(), 2);
+
+        // As it does after a power cycle, once restored from the settings.
+        let mut restored = Profiles::new(profiles.active_index(), profiles.selected_manually());
+        restored.select_for_host_os(HostOs::MacOs);
+        assert_eq!(restored.active_index(), 2);
+        assert!(restored.take_action().is_none());
+    }
+}
@@ -0,0 +1,203 @@
+//! Repeat keys, which send the last key pressed again, or its counterpart.
+
+use crate::{
+    action::Action,
+    key_codes::KeyCode,
+    key_mapping::ALTERNATE_REPEATS,
+    key_scan::{KeyScan, KeyboardReport},
+    NUM_COLS, NUM_ROWS,
+};
+
+/// A pair of actions which are each other's alternate, see `ALTERNATE_REPEATS` in
+/// `key_mapping.rs`. Only `Action::Key` and `Action::Modified` are matched.
+pub struct AlternateRepeat {
+    pub key: Action,
+    pub alternate: Action,
+}
+
+/// Returns the `MOD_*` bitmask and key sent by `action`.
+fn modified_key(action: Action) -> Option<(u8, KeyCode)> {
+    match action {
+        Action::Key(key) => Some((0, key)),
+        Action::Modified(modifiers, key) => Some((modifiers, key)),
+        _ => None,
+    }
+}
+
+/// Returns the alternate of `key` sent with `modifiers`, from `ALTERNATE_REPEATS`.
+fn alternate(modifiers: u8, key: KeyCode) -> Option<(u8, KeyCode)> {
+    // The `MOD_*` bitmasks only have the left-hand modifiers.
+    let modifiers = (modifiers | modifiers >> 4) & 0x0F;
+
+    ALTERNATE_REPEATS.iter().find_map(|pair| {
+        if modified_key(pair.key) == Some((modifiers, key)) {
+            modified_key(pair.alternate)
+        } else if modified_key(pair.alternate) == Some((modifiers, key)) {
+            modified_key(pair.key)
+        } else {
+            None
+        }
+    })
+}
+
+/// `Repeat` remembers the last key newly pressed in the report (other than a modifier),
+/// together with the modifiers held at the time.
+///
+/// While the `Repeat` key is held that key is sent again, and while the `AlternateRepeat`
+/// key is held its alternate from `ALTERNATE_REPEATS` is sent instead.
+pub struct Repeat {
+    /// The modifiers and key which were last pressed.
+    last: Option<(u8, KeyCode)>,
+
+    /// Whether the `Repeat` and `AlternateRepeat` keys are held.
+    repeat_held: bool,
+    alternate_held: bool,
+
+    /// The keys in the previous report, used to find newly pressed keys.
+    previous_keycodes: [u8; 6],
+}
+
+impl Repeat {
+    pub fn new() -> Self {
+        Self { last: None, repeat_held: false, alternate_held: false, previous_keycodes: [0; 6] }
+    }
+
+    /// Removes the `Repeat` and `AlternateRepeat` keys from `scan`, noting whether they're held.
+    pub fn tick(&mut self, scan: &mut KeyScan<NUM_ROWS, NUM_COLS>) {
+        let layer_mapping = scan.layer_mapping();
+        self.repeat_held = false;
+        self.alternate_held = false;
+
+        for (matrix_column, mapping_column) in scan.iter_mut().zip(layer_mapping) {
+            for (key_pressed, mapping_row) in matrix_column.iter_mut().zip(mapping_column) {
+                match mapping_row {
+                    Action::Repeat => self.repeat_held |= *key_pressed,
+                    Action::AlternateRepeat => self.alternate_held |= *key_pressed,
+                    _ => continue,
+                }
+
+                *key_pressed = false;
+            }
+        }
+    }
+
+    /// Remembers the last key newly pressed in `report`, then adds the repeated key to it if
+    /// a repeat key is held.
+    pub fn apply(&mut self, report: &mut KeyboardReport) {
+        for keycode in report.keycodes {
+            if keycode != 0 && !self.previous_keycodes.contains(&keycode) {
+                if let Ok(key) = KeyCode::try_from(keycode) {
+                    self.last = Some((report.modifier, key));
+                }
+            }
+        }
+
+        self.previous_keycodes = report.keycodes;
+
+        let Some((modifiers, key)) = self.last else {
+            return;
+        };
+
+        let repeated = if self.repeat_held {
+            Some((modifiers, key))
+        } else if self.alternate_held {
+            alternate(modifiers, key)
+        } else {
+            None
+        };
+
+        if let Some((modifiers, key)) = repeated {
+            report.modifier |= modifiers;
+            report.press(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_mapping::PROFILES, key_scan::Layer};
+
+    /// Runs a tick with the keys at `positions` pressed, returning the report.
+    fn run(repeat: &mut Repeat, positions: &[(usize, usize)]) -> KeyboardReport {
+        let mut scan = KeyScan::with_pressed(&PROFILES[0], positions);
+        repeat.tick(&mut scan);
+
+        let mut report = KeyboardReport::default();
+        let layer_mapping = scan.layer_mapping();
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                if scan[col][row] {
+                    report.press_action(layer_mapping[col][row]);
+                }
+            }
+        }
+
+        repeat.apply(&mut report);
+        report
+    }
+
+    /// Taps the keys at `positions` together.
+    fn tap(repeat: &mut Repeat, positions: &[(usize, usize)]) {
+        run(repeat, positions);
+        run(repeat, &[]);
+    }
+
+    /// Presses the key mapped to `action` on the Fn layer, returning the report.
+    fn press_fn(repeat: &mut Repeat, action: Action) -> KeyboardReport {
+        let fn_key = PROFILES[0]
+            .find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(Layer::Fn)));
+        let position = PROFILES[0].find(Layer::Fn, |candidate| {
+            core::mem::discriminant(&candidate) == core::mem::discriminant(&action)
+        });
+
+        let report = run(repeat, &[fn_key, position]);
+        run(repeat, &[]);
+        report
+    }
+
+    fn key(key: KeyCode) -> (usize, usize) {
+        PROFILES[0].find(Layer::Normal, |action| action.key() == Some(key))
+    }
+
+    fn report(keys: &[KeyCode]) -> KeyboardReport {
+        let mut report = KeyboardReport::default();
+        for key in keys {
+            report.press(*key);
+        }
+
+        report
+    }
+
+    #[test]
+    fn repeat_sends_the_last_key_with_its_modifiers() {
+        let mut repeat = Repeat::new();
+        tap(&mut repeat, &[key(KeyCode::LeftCtrl), key(KeyCode::Z)]);
+
+        assert_eq!(press_fn(&mut repeat, Action::Repeat), report(&[KeyCode::LeftCtrl, KeyCode::Z]));
+    }
+
+    #[test]
+    fn alternate_repeat_sends_the_alternate_key() {
+        let mut repeat = Repeat::new();
+        tap(&mut repeat, &[key(KeyCode::LeftCtrl), key(KeyCode::Z)]);
+        assert_eq!(
+            press_fn(&mut repeat, Action::AlternateRepeat),
+            report(&[KeyCode::LeftCtrl, KeyCode::Y])
+        );
+
+        // The pairs work both ways.
+        tap(&mut repeat, &[key(KeyCode::Down)]);
+        assert_eq!(press_fn(&mut repeat, Action::AlternateRepeat), report(&[KeyCode::Up]));
+    }
+
+    #[test]
+    fn repeated_keys_are_not_remembered() {
+        let mut repeat = Repeat::new();
+        tap(&mut repeat, &[key(KeyCode::Up)]);
+
+        assert_eq!(press_fn(&mut repeat, Action::AlternateRepeat), report(&[KeyCode::Down]));
+        assert_eq!(press_fn(&mut repeat, Action::AlternateRepeat), report(&[KeyCode::Down]));
+        assert_eq!(press_fn(&mut repeat, Action::Repeat), report(&[KeyCode::Up]));
+    }
+}
@@ -0,0 +1,189 @@
+//! Press-order tracking, to decide which keys make it into the six keycode slots of a
+//! `KeyboardReport` when more than six keys are held.
+
+use crate::{
+    action::Action,
+    key_codes::KeyCode,
+    key_scan::{KeyScan, KeyboardReport},
+    NUM_COLS, NUM_ROWS,
+};
+
+/// What to report when more keys are held than fit in a `KeyboardReport`.
+#[allow(unused)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum RolloverPolicy {
+    /// Report the keys which were pressed first.
+    KeepOldest,
+    /// Report the keys which were pressed most recently.
+    KeepNewest,
+    /// Fill every keycode slot with `ErrorRollOver`, as described in the HID spec.
+    ErrorRollOver,
+}
+
+/// `Rollover` remembers the order in which keys were pressed, and builds reports where the
+/// keycodes are in press order rather than matrix order.
+pub struct Rollover {
+    policy: RolloverPolicy,
+
+    /// For each key, the value of `press_count` when it was last pressed.
+    pressed_at: [[u32; NUM_ROWS]; NUM_COLS],
+
+    /// The number of key presses seen so far.
+    press_count: u32,
+
+    /// The matrix from the previous tick, used to find newly pressed keys.
+    previous_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+}
+
+impl Rollover {
+    pub fn new(policy: RolloverPolicy) -> Self {
+        Self {
+            policy,
+            pressed_at: [[0; NUM_ROWS]; NUM_COLS],
+            press_count: 0,
+            previous_matrix: [[false; NUM_ROWS]; NUM_COLS],
+        }
+    }
+
+    /// Records the press order of keys which were newly pressed in `scan`.
+    pub fn tick(&mut self, scan: &KeyScan<NUM_ROWS, NUM_COLS>) {
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                if scan[col][row] && !self.previous_matrix[col][row] {
+                    self.press_count = self.press_count.wrapping_add(1);
+                    self.pressed_at[col][row] = self.press_count;
+                }
+            }
+        }
+
+        self.previous_matrix = **scan;
+    }
+
+    /// Builds the report for `scan`, applying the rollover policy if more than six
+    /// non-modifier keys are held.
+    pub fn report(&self, scan: &KeyScan<NUM_ROWS, NUM_COLS>) -> KeyboardReport {
+        let layer_mapping = scan.layer_mapping();
+        let mut report = KeyboardReport::default();
+
+        let mut pressed_keys = [(0, Action::Key(KeyCode::Empty)); NUM_ROWS * NUM_COLS];
+        let mut num_pressed = 0;
+
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                if !scan[col][row] {
+                    continue;
+                }
+
+                match layer_mapping[col][row] {
+                    action @ (Action::Key(key) | Action::Modified(_, key))
+                        if key != KeyCode::Empty && !key.is_modifier() =>
+                    {
+                        pressed_keys[num_pressed] = (self.pressed_at[col][row], action);
+                        num_pressed += 1;
+                    },
+                    action @ (Action::GraveEscape | Action::IfLed(..)) => {
+                        pressed_keys[num_pressed] = (self.pressed_at[col][row], action);
+                        num_pressed += 1;
+                    },
+                    action => report.press_action(action),
+                }
+            }
+        }
+
+        let pressed_keys = &mut pressed_keys[..num_pressed];
+
+        // Now that all the modifiers are known, resolve the keys which depend on them.
+        for (_, action) in pressed_keys.iter_mut() {
+            *action = action.resolve(report.modifier, scan.leds());
+        }
+
+        pressed_keys.sort_unstable_by_key(|(pressed_at, _)| *pressed_at);
+
+        let num_slots = report.keycodes.len();
+        let reported_keys = if pressed_keys.len() <= num_slots {
+            &pressed_keys[..]
+        } else {
+            match self.policy {
+                RolloverPolicy::KeepOldest => &pressed_keys[..num_slots],
+                RolloverPolicy::KeepNewest => &pressed_keys[pressed_keys.len() - num_slots..],
+                RolloverPolicy::ErrorRollOver => {
+                    report.keycodes = [KeyCode::ErrorRollOver as u8; 6];
+                    &[]
+                },
+            }
+        };
+
+        for (_, action) in reported_keys {
+            report.press_action(*action);
+        }
+
+        for (matrix_column, mapping_column) in scan.iter().zip(layer_mapping) {
+            for (key_pressed, mapping_row) in matrix_column.iter().zip(mapping_column) {
+                if let (Action::Custom(callback), true) = (mapping_row, *key_pressed) {
+                    callback(&mut report);
+                }
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_mapping::PROFILES, key_scan::Layer};
+
+    const KEYS: [KeyCode; 7] =
+        [KeyCode::A, KeyCode::S, KeyCode::D, KeyCode::F, KeyCode::J, KeyCode::K, KeyCode::L];
+
+    /// Presses `KEYS` one per tick while holding Shift, returning the final report.
+    fn press_all(policy: RolloverPolicy) -> KeyboardReport {
+        let mut rollover = Rollover::new(policy);
+        let mut positions = vec![
+            PROFILES[0].find(Layer::Normal, |action| action.key() == Some(KeyCode::LeftShift))
+        ];
+
+        let mut scan = KeyScan::with_pressed(&PROFILES[0], &positions);
+        for key in KEYS {
+            positions.push(PROFILES[0].find(Layer::Normal, |action| action.key() == Some(key)));
+            scan = KeyScan::with_pressed(&PROFILES[0], &positions);
+            rollover.tick(&scan);
+        }
+
+        rollover.report(&scan)
+    }
+
+    fn keycodes(keys: &[KeyCode]) -> [u8; 6] {
+        let mut keycodes = [0; 6];
+        for (slot, key) in keycodes.iter_mut().zip(keys) {
+            *slot = *key as u8;
+        }
+
+        keycodes
+    }
+
+    #[test]
+    fn keep_oldest_reports_the_first_six_keys_in_press_order() {
+        let report = press_all(RolloverPolicy::KeepOldest);
+
+        assert_eq!(report.keycodes, keycodes(&KEYS[..6]));
+        assert_eq!(report.modifier, KeyCode::LeftShift.modifier_bitmask().unwrap());
+    }
+
+    #[test]
+    fn keep_newest_reports_the_last_six_keys_in_press_order() {
+        let report = press_all(RolloverPolicy::KeepNewest);
+
+        assert_eq!(report.keycodes, keycodes(&KEYS[1..]));
+        assert_eq!(report.modifier, KeyCode::LeftShift.modifier_bitmask().unwrap());
+    }
+
+    #[test]
+    fn error_roll_over_fills_every_slot_but_keeps_the_modifiers() {
+        let report = press_all(RolloverPolicy::ErrorRollOver);
+
+        assert_eq!(report.keycodes, [KeyCode::ErrorRollOver as u8; 6]);
+        assert_eq!(report.modifier, KeyCode::LeftShift.modifier_bitmask().unwrap());
+    }
+}
@@ -0,0 +1,479 @@
+//! Settings which are kept in flash across power cycles, such as the selected profile.
+//!
+//! The settings region (`SETTINGS` in `memory.x`) is used as a log. Every save programs the
+//! next page with a snapshot of all the settings, and at startup the valid snapshot with
+//! the highest sequence number is loaded. The pages are written in turn through every
+//! sector of the region to spread the wear, and a sector is only erased once the log wraps
+//! around to it. A save cut short by a power loss fails its CRC, so the previous snapshot is
+//! loaded instead.
+//!
+//! The flash itself is reached through `SettingsFlash`, which `flash.rs` implements for the
+//! real chip, so the log can be tested on the host.
+
+use crate::{
+    flash::{PAGE_SIZE, SECTOR_SIZE, SETTINGS_REGION_SIZE},
+    key_mapping::PROFILES,
+};
+use defmt::{info, warn};
+
+const PAGES: usize = SETTINGS_REGION_SIZE / PAGE_SIZE;
+const PAGES_PER_SECTOR: usize = SECTOR_SIZE / PAGE_SIZE;
+
+/// Marks a page holding a snapshot.
+const MAGIC: [u8; 4] = *b"KRST";
+
+/// The version of the snapshot layout. Bump it when the meaning of a stored key changes,
+/// and add a migration from the previous version to `MIGRATIONS`.
+const SCHEMA_VERSION: u16 = 1;
+
+/// Upgrades settings saved by older firmware, `MIGRATIONS[n]` from version `n + 1` to
+/// `n + 2`. Keys which are added or removed don't need a migration.
+const MIGRATIONS: &[fn(&mut Settings)] = &[];
+
+const _: () = assert!(MIGRATIONS.len() + 1 == SCHEMA_VERSION as usize);
+
+// A snapshot page is laid out as:
+//   0..4     MAGIC
+//   4..8     Sequence number (little endian)
+//   8..10    SCHEMA_VERSION (little endian)
+//   10..12   Payload length (little endian)
+//   12..     Payload, a list of (key, value length, value) entries
+//   252..256 CRC-32 of bytes 0..252 (little endian)
+const HEADER_LEN: usize = 12;
+const CRC_OFFSET: usize = PAGE_SIZE - 4;
+
+// The keys of the settings in a snapshot's payload. Keys are never reused.
+const PROFILE_KEY: u8 = 1;
+const AUTO_SHIFT_ENABLED_KEY: u8 = 2;
+const AUTOCORRECT_ENABLED_KEY: u8 = 3;
+const PROFILE_SELECTED_MANUALLY_KEY: u8 = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// The index of the active profile in `PROFILES`.
+    pub profile: usize,
+    /// Whether the profile was selected with a `SelectProfile` key rather than detected.
+    pub profile_selected_manually: bool,
+    pub auto_shift_enabled: bool,
+    pub autocorrect_enabled: bool,
+}
+
+impl Settings {
+    /// Writes the settings to `payload`, returning the number of bytes written.
+    fn encode(&self, payload: &mut [u8]) -> usize {
+        let entries = [
+            (PROFILE_KEY, self.profile as u8),
+            (PROFILE_SELECTED_MANUALLY_KEY, self.profile_selected_manually as u8),
+            (AUTO_SHIFT_ENABLED_KEY, self.auto_shift_enabled as u8),
+            (AUTOCORRECT_ENABLED_KEY, self.autocorrect_enabled as u8),
+        ];
+
+        let mut len = 0;
+        for (key, value) in entries {
+            payload[len..len + 3].copy_from_slice(&[key, 1, value]);
+            len += 3;
+        }
+
+        len
+    }
+
+    /// Reads the settings in `payload`. Settings missing from it, or with values this firmware
+    /// can't use, are left as they are.
+    fn decode(&mut self, payload: &[u8]) {
+        let mut rest = payload;
+        while let [key, len, ..] = *rest {
+            let Some(value) = rest.get(2..2 + len as usize) else {
+                break;
+            };
+
+            match (key, value) {
+                (PROFILE_KEY, &[profile]) if (profile as usize) < PROFILES.len() => {
+                    self.profile = profile as usize;
+                },
+                (PROFILE_SELECTED_MANUALLY_KEY, &[manually]) => {
+                    self.profile_selected_manually = manually != 0;
+                },
+                (AUTO_SHIFT_ENABLED_KEY, &[enabled]) => self.auto_shift_enabled = enabled != 0,
+                (AUTOCORRECT_ENABLED_KEY, &[enabled]) => self.autocorrect_enabled = enabled != 0,
+                _ => {},
+            }
+
+            rest = &rest[2 + len as usize..];
+        }
+    }
+}
+
+/// The CRC-32 (IEEE) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+/// Returns the sequence number, schema version and payload of the snapshot in `page`, if
+/// it holds a valid one.
+fn read_snapshot(page: &[u8; PAGE_SIZE]) -> Option<(u32, u16, &[u8])> {
+    let crc = u32::from_le_bytes(page[CRC_OFFSET..].try_into().unwrap());
+    if page[0..4] != MAGIC || crc32(&page[..CRC_OFFSET]) != crc {
+        return None;
+    }
+
+    let sequence = u32::from_le_bytes(page[4..8].try_into().unwrap());
+    let version = u16::from_le_bytes(page[8..10].try_into().unwrap());
+    if version == 0 {
+        return None;
+    }
+
+    let len = u16::from_le_bytes(page[10..12].try_into().unwrap()) as usize;
+    let payload = page[HEADER_LEN..CRC_OFFSET].get(..len)?;
+
+    Some((sequence, version, payload))
+}
+
+/// Returns a page holding a snapshot of `settings` with the `sequence` number.
+fn write_snapshot(sequence: u32, settings: &Settings) -> [u8; PAGE_SIZE] {
+    let mut page = [0xFF; PAGE_SIZE];
+    page[0..4].copy_from_slice(&MAGIC);
+    page[4..8].copy_from_slice(&sequence.to_le_bytes());
+    page[8..10].copy_from_slice(&SCHEMA_VERSION.to_le_bytes());
+    let len = settings.encode(&mut page[HEADER_LEN..CRC_OFFSET]);
+    page[10..12].copy_from_slice(&(len as u16).to_le_bytes());
+    let crc = crc32(&page[..CRC_OFFSET]);
+    page[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+
+    page
+}
+
+/// Upgrades `settings` saved with schema `version` to `SCHEMA_VERSION` with `migrations`
+/// (`MIGRATIONS` outside of tests).
+fn migrate(settings: &mut Settings, version: u16, migrations: &[fn(&mut Settings)]) {
+    for migration in &migrations[version as usize - 1..] {
+        migration(settings);
+    }
+}
+
+/// The settings region of flash, addressed by the index of a page in the region.
+pub trait SettingsFlash {
+    fn read_page(&self, page: usize) -> &[u8; PAGE_SIZE];
+
+    /// Erases the sector starting at `page`, setting every byte to 0xFF.
+    fn erase_sector(&mut self, page: usize);
+
+    /// Programs the erased `page` with `data`.
+    fn program_page(&mut self, page: usize, data: &[u8; PAGE_SIZE]);
+}
+
+/// Returns the page, sequence number, schema version and payload of the valid snapshot with
+/// the highest sequence number in `flash`.
+fn find_newest<F: SettingsFlash>(flash: &F) -> Option<(usize, u32, u16, &[u8])> {
+    let mut newest: Option<(usize, u32, u16, &[u8])> = None;
+    for page in 0..PAGES {
+        if let Some((sequence, version, payload)) = read_snapshot(flash.read_page(page)) {
+            if newest.is_none_or(|(_, newest_sequence, _, _)| sequence > newest_sequence) {
+                newest = Some((page, sequence, version, payload));
+            }
+        }
+    }
+
+    newest
+}
+
+/// `SettingsStore` loads the settings from flash at startup, and saves them again once
+/// they've changed and then stayed the same for a while, so toggling something back and
+/// forth doesn't wear out the flash.
+pub struct SettingsStore<F: SettingsFlash> {
+    flash: F,
+
+    /// The page holding the newest snapshot, and its sequence number.
+    newest: Option<(usize, u32)>,
+
+    /// The settings in the newest snapshot.
+    saved: Settings,
+
+    /// The settings on the previous tick, and the number of ticks they've stayed the same.
+    latest: Settings,
+    unchanged_ticks: u16,
+
+    /// The number of ticks changed settings have to stay the same before they're saved.
+    save_delay_ticks: u16,
+}
+
+impl<F: SettingsFlash> SettingsStore<F> {
+    /// Loads the newest snapshot from `flash`. Settings it doesn't have are taken from
+    /// `defaults`, as are all of them if there's no snapshot or it was saved by newer
+    /// firmware.
+    pub fn load(flash: F, defaults: Settings, save_delay_ticks: u16) -> Self {
+        let newest = find_newest(&flash);
+
+        let mut settings = defaults;
+        match newest {
+            Some((_, _, version, _)) if version > SCHEMA_VERSION => {
+                warn!("Ignoring settings saved with the newer schema version {}", version);
+            },
+            Some((_, _, version, payload)) => {
+                settings.decode(payload);
+                migrate(&mut settings, version, MIGRATIONS);
+
+                info!("Loaded settings (schema version {})", version);
+            },
+            None => info!("No saved settings, using the defaults"),
+        }
+
+        let newest = newest.map(|(page, sequence, _, _)| (page, sequence));
+        Self {
+            flash,
+            newest,
+            saved: settings,
+            latest: settings,
+            unchanged_ticks: 0,
+            save_delay_ticks,
+        }
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.saved
+    }
+
+    /// Saves `settings` once they've changed and then stayed the same for the save delay.
+    pub fn tick(&mut self, settings: Settings) {
+        if settings != self.latest {
+            self.latest = settings;
+            self.unchanged_ticks = 0;
+            return;
+        }
+
+        if settings == self.saved {
+            return;
+        }
+
+        self.unchanged_ticks += 1;
+        if self.unchanged_ticks >= self.save_delay_ticks {
+            self.save(settings);
+        }
+    }
+
+    fn save(&mut self, settings: Settings) {
+        let sequence = self.newest.map_or(0, |(_, sequence)| sequence.wrapping_add(1));
+
+        // Write to the next erased page after the newest snapshot. Pages left dirty by an
+        // interrupted save are skipped, and the sector after the newest snapshot's is erased
+        // once the log reaches it, as it only holds older snapshots.
+        let mut page = self.newest.map_or(0, |(page, _)| (page + 1) % PAGES);
+        loop {
+            if page % PAGES_PER_SECTOR == 0 {
+                self.flash.erase_sector(page);
+                break;
+            }
+
+            if self.flash.read_page(page).iter().all(|byte| *byte == 0xFF) {
+                break;
+            }
+
+            page = (page + 1) % PAGES;
+        }
+
+        self.flash.program_page(page, &write_snapshot(sequence, &settings));
+        info!("Saved settings to page {} of the settings region", page);
+
+        self.newest = Some((page, sequence));
+        self.saved = settings;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A settings region in RAM. Like flash, programming can only clear bits.
+    struct TestFlash {
+        pages: Vec<[u8; PAGE_SIZE]>,
+
+        /// The first page of each sector erased, in order.
+        erased: Vec<usize>,
+    }
+
+    impl TestFlash {
+        fn new() -> Self {
+            Self { pages: vec![[0xFF; PAGE_SIZE]; PAGES], erased: Vec::new() }
+        }
+    }
+
+    impl SettingsFlash for TestFlash {
+        fn read_page(&self, page: usize) -> &[u8; PAGE_SIZE] {
+            &self.pages[page]
+        }
+
+        fn erase_sector(&mut self, page: usize) {
+            assert_eq!(page % PAGES_PER_SECTOR, 0);
+            self.pages[page..page + PAGES_PER_SECTOR].fill([0xFF; PAGE_SIZE]);
+            self.erased.push(page);
+        }
+
+        fn program_page(&mut self, page: usize, data: &[u8; PAGE_SIZE]) {
+            for (byte, data) in self.pages[page].iter_mut().zip(data) {
+                *byte &= data;
+            }
+        }
+    }
+
+    const DEFAULTS: Settings = Settings {
+        profile: 0,
+        profile_selected_manually: false,
+        auto_shift_enabled: false,
+        autocorrect_enabled: true,
+    };
+
+    fn settings(profile: usize) -> Settings {
+        Settings {
+            profile,
+            profile_selected_manually: true,
+            auto_shift_enabled: true,
+            autocorrect_enabled: false,
+        }
+    }
+
+    #[test]
+    fn settings_survive_encoding() {
+        let mut payload = [0; 32];
+        let len = settings(2).encode(&mut payload);
+
+        let mut decoded = DEFAULTS;
+        decoded.decode(&payload[..len]);
+        assert_eq!(decoded, settings(2));
+    }
+
+    #[test]
+    fn unknown_keys_and_unusable_values_are_skipped() {
+        #[rustfmt::skip]
+        let payload = [
+            // A key added by newer firmware, with a 2 byte value.
+            99, 2, 1, 2,
+            // A profile this firmware doesn't have.
+            PROFILE_KEY, 1, PROFILES.len() as u8,
+            AUTO_SHIFT_ENABLED_KEY, 1, 1,
+            // Cut short.
+            AUTOCORRECT_ENABLED_KEY, 1,
+        ];
+
+        let mut decoded = DEFAULTS;
+        decoded.decode(&payload);
+        assert_eq!(decoded, Settings { auto_shift_enabled: true, ..DEFAULTS });
+    }
+
+    #[test]
+    fn crc32_matches_the_ieee_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn snapshots_with_a_bad_crc_or_magic_are_invalid() {
+        let page = write_snapshot(7, &settings(1));
+        assert!(read_snapshot(&page)
+            .is_some_and(|(sequence, version, _)| { sequence == 7 && version == SCHEMA_VERSION }));
+
+        let mut corrupted = page;
+        corrupted[HEADER_LEN] ^= 1;
+        assert!(read_snapshot(&corrupted).is_none());
+
+        let mut unmarked = page;
+        unmarked[0] = 0;
+        assert!(read_snapshot(&unmarked).is_none());
+    }
+
+    #[test]
+    fn migrations_run_from_the_saved_version_on() {
+        let migrations: &[fn(&mut Settings)] =
+            &[|settings| settings.profile += 1, |settings| settings.profile *= 10];
+
+        let mut from_first = settings(1);
+        migrate(&mut from_first, 1, migrations);
+        assert_eq!(from_first.profile, 20);
+
+        let mut from_second = settings(1);
+        migrate(&mut from_second, 2, migrations);
+        assert_eq!(from_second.profile, 10);
+
+        let mut current = settings(1);
+        migrate(&mut current, 3, migrations);
+        assert_eq!(current.profile, 1);
+    }
+
+    #[test]
+    fn the_newest_valid_snapshot_is_loaded() {
+        let mut flash = TestFlash::new();
+        flash.pages[3] = write_snapshot(5, &settings(1));
+        flash.pages[4] = write_snapshot(6, &settings(2));
+        flash.pages[1] = write_snapshot(4, &settings(0));
+
+        // A save cut short by a power loss.
+        flash.pages[5] = write_snapshot(7, &settings(0));
+        flash.pages[5][CRC_OFFSET] ^= 1;
+
+        let store = SettingsStore::load(flash, DEFAULTS, 1);
+        assert_eq!(store.settings(), settings(2));
+        assert_eq!(store.newest, Some((4, 6)));
+    }
+
+    #[test]
+    fn snapshots_from_newer_firmware_are_ignored() {
+        let mut flash = TestFlash::new();
+        flash.pages[0] = write_snapshot(0, &settings(1));
+        flash.pages[0][8..10].copy_from_slice(&(SCHEMA_VERSION + 1).to_le_bytes());
+        let crc = crc32(&flash.pages[0][..CRC_OFFSET]);
+        flash.pages[0][CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+
+        assert_eq!(SettingsStore::load(flash, DEFAULTS, 1).settings(), DEFAULTS);
+    }
+
+    #[test]
+    fn saves_are_delayed_until_the_settings_stop_changing() {
+        let mut store = SettingsStore::load(TestFlash::new(), DEFAULTS, 3);
+        store.tick(settings(1));
+        store.tick(settings(2));
+        store.tick(settings(2));
+        store.tick(settings(2));
+        assert!(store.newest.is_none());
+
+        store.tick(settings(2));
+        assert_eq!(store.newest, Some((0, 0)));
+        assert_eq!(SettingsStore::load(store.flash, DEFAULTS, 3).settings(), settings(2));
+    }
+
+    #[test]
+    fn the_log_wraps_around_the_region_erasing_each_sector_as_it_gets_there() {
+        let mut store = SettingsStore::load(TestFlash::new(), DEFAULTS, 1);
+        let sectors = PAGES / PAGES_PER_SECTOR;
+
+        for save in 0..PAGES + 2 {
+            store.save(settings(save % PROFILES.len()));
+        }
+
+        // Every sector was erased as the log reached it, then the first one again.
+        let expected: Vec<_> =
+            (0..=sectors).map(|sector| sector % sectors * PAGES_PER_SECTOR).collect();
+        assert_eq!(store.flash.erased, expected);
+        assert_eq!(store.newest, Some((1, PAGES as u32 + 1)));
+
+        let loaded = SettingsStore::load(store.flash, DEFAULTS, 1);
+        assert_eq!(loaded.settings(), settings((PAGES + 1) % PROFILES.len()));
+        assert_eq!(loaded.newest, Some((1, PAGES as u32 + 1)));
+    }
+
+    #[test]
+    fn pages_left_dirty_by_an_interrupted_save_are_skipped() {
+        let mut flash = TestFlash::new();
+        flash.pages[0] = write_snapshot(0, &settings(1));
+        flash.pages[1][0] = 0;
+
+        let mut store = SettingsStore::load(flash, DEFAULTS, 1);
+        store.save(settings(2));
+        assert_eq!(store.newest, Some((2, 1)));
+        assert!(store.flash.erased.is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+//! Space Cadet keys, which act as a modifier when held, but type something else (such as
+//! a parenthesis) when tapped on their own.
+
+use crate::{key_codes::KeyCode, key_scan::KeyScan, macros::Macro, NUM_COLS, NUM_ROWS};
+
+/// The number of Space Cadet keys a profile can have.
+pub const MAX_SPACE_CADET_KEYS: usize = 4;
+
+/// A modifier with a tap action, see `*_SPACE_CADET_KEYS` in `key_mapping.rs`.
+pub struct SpaceCadetKey {
+    /// The modifier key in the layer mappings.
+    pub key: KeyCode,
+
+    /// The macro played when the key is tapped without pressing anything else.
+    pub tap: Macro,
+}
+
+#[derive(Copy, Clone, Default)]
+struct SpaceCadetState {
+    /// Whether the key was pressed on the previous tick.
+    pressed: bool,
+
+    /// Whether the key is being used as a modifier, because another key was pressed or it
+    /// was held for too long.
+    interrupted: bool,
+
+    /// The number of ticks the key has been held for.
+    held_ticks: u16,
+}
+
+/// `SpaceCadet` holds back the active profile's Space Cadet modifiers while they are pressed on
+/// their own, so a bare tap doesn't leak a modifier press to the host.
+///
+/// As soon as another key is pressed, or the modifier is held for `timeout_ticks`, it's
+/// reported as a regular modifier. Releasing it before then plays its tap macro instead.
+pub struct SpaceCadet {
+    /// The state of each of the active profile's Space Cadet keys.
+    states: [SpaceCadetState; MAX_SPACE_CADET_KEYS],
+
+    /// The Space Cadet keys of the profile active on the previous tick.
+    keys: &'static [SpaceCadetKey],
+
+    /// The tap macro of a key which was just tapped, until it is taken by `take_action`.
+    action: Option<Macro>,
+
+    /// The matrix from the previous tick, used to find newly pressed keys.
+    previous_matrix: [[bool; NUM_ROWS]; NUM_COLS],
+
+    timeout_ticks: u16,
+}
+
+impl SpaceCadet {
+    pub fn new(timeout_ticks: u16) -> Self {
+        Self {
+            states: [SpaceCadetState::default(); MAX_SPACE_CADET_KEYS],
+            keys: &[],
+            action: None,
+            previous_matrix: [[false; NUM_ROWS]; NUM_COLS],
+            timeout_ticks,
+        }
+    }
+
+    /// Removes Space Cadet keys which are still undecided from `scan`, and advances their
+    /// state by one tick.
+    pub fn tick(&mut self, scan: &mut KeyScan<NUM_ROWS, NUM_COLS>) {
+        let layer_mapping = scan.layer_mapping();
+
+        let keys = scan.profile().space_cadet_keys;
+        if !core::ptr::eq(keys, self.keys) {
+            self.keys = keys;
+            self.states = [SpaceCadetState::default(); MAX_SPACE_CADET_KEYS];
+        }
+
+        let previous_matrix = core::mem::replace(&mut self.previous_matrix, **scan);
+
+        let mut other_key_pressed = false;
+        for col in 0..NUM_COLS {
+            for row in 0..NUM_ROWS {
+                let key = layer_mapping[col][row].key();
+                let newly_pressed = scan[col][row] && !previous_matrix[col][row];
+
+                if newly_pressed && !keys.iter().any(|cadet| Some(cadet.key) == key) {
+                    other_key_pressed = true;
+                }
+            }
+        }
+
+        for (cadet, state) in keys.iter().zip(self.states.iter_mut()) {
+            let mut pressed = false;
+            let mut newly_pressed = false;
+            for col in 0..NUM_COLS {
+                for row in 0..NUM_ROWS {
+                    if layer_mapping[col][row].key() == Some(cadet.key) && scan[col][row] {
+                        pressed = true;
+                        newly_pressed |= !previous_matrix[col][row];
+                    }
+                }
+            }
+
+            if pressed && !state.pressed {
+                // A key which was already held under another meaning, like a thumb key of a
+                // layer rule when the other one is released, isn't tapped either.
+                state.interrupted = other_key_pressed || !newly_pressed;
+                state.held_ticks = 0;
+            } else if pressed {
+                state.held_ticks = state.held_ticks.saturating_add(1);
+                state.interrupted |= other_key_pressed || state.held_ticks >= self.timeout_ticks;
+            } else if state.pressed && !(state.interrupted || other_key_pressed) {
+                // A key pressed as this one goes away, such as the other thumb key of a layer
+                // rule which maps this one to something else, isn't a tap either.
+                self.action = Some(cadet.tap);
+            }
+
+            state.pressed = pressed;
+
+            if pressed && !state.interrupted {
+                for (matrix_column, mapping_column) in scan.iter_mut().zip(layer_mapping) {
+                    for (key_pressed, mapping_row) in matrix_column.iter_mut().zip(mapping_column) {
+                        if mapping_row.key() == Some(cadet.key) {
+                            *key_pressed = false;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the tap macro of a key which was just tapped, if any.
+    pub fn take_action(&mut self) -> Option<Macro> {
+        self.action.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        key_mapping::PROFILES, key_scan::Layer, macros::MacroStep::Text, profile::Profile,
+    };
+
+    /// Taps the key at `position` on its own, returning the macro played.
+    fn tap(profile: &'static Profile, position: (usize, usize)) -> Option<Macro> {
+        let mut space_cadet = SpaceCadet::new(100);
+        space_cadet.tick(&mut KeyScan::with_pressed(profile, &[position]));
+        space_cadet.tick(&mut KeyScan::with_pressed(profile, &[]));
+        space_cadet.take_action()
+    }
+
+    #[test]
+    fn the_same_keys_tap_the_same_macros_in_every_profile() {
+        let macos = &PROFILES[0];
+        for cadet in macos.space_cadet_keys {
+            let position = macos.find(Layer::Normal, |action| action.key() == Some(cadet.key));
+
+            for profile in PROFILES {
+                let tapped = tap(profile, position);
+                assert!(tapped.is_some(), "{}", profile.name);
+
+                // The thumb keys tap themselves, which differ between profiles.
+                if matches!(cadet.tap, [Text(_)]) {
+                    assert_eq!(tapped, Some(cadet.tap), "{}", profile.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn thumb_keys_arent_sent_before_the_chord() {
+        for (profile, left, right) in [
+            (&PROFILES[0], KeyCode::LeftCmd, KeyCode::RightCmd),
+            (&PROFILES[1], KeyCode::LeftAlt, KeyCode::RightAlt),
+        ] {
+            let left = profile.find(Layer::Normal, |action| action.key() == Some(left));
+            let right = profile.find(Layer::Normal, |action| action.key() == Some(right));
+            let mut space_cadet = SpaceCadet::new(100);
+
+            let mut scan = KeyScan::with_pressed(profile, &[left]);
+            space_cadet.tick(&mut scan);
+            assert!(!scan[left.0][left.1], "{}", profile.name);
+
+            let mut scan = KeyScan::with_pressed(profile, &[left, right]);
+            space_cadet.tick(&mut scan);
+            assert!(scan.layer() == Layer::Symbol, "{}", profile.name);
+
+            // Releasing the thumb keys one after the other doesn't tap the one held last.
+            space_cadet.tick(&mut KeyScan::with_pressed(profile, &[right]));
+            space_cadet.tick(&mut KeyScan::with_pressed(profile, &[]));
+            assert!(space_cadet.take_action().is_none(), "{}", profile.name);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Tap-dance keys, which send a different key depending on how many times they are
+//! tapped in quick succession, or on whether they are held down.
+
+use crate::{
+    action::Action,
+    key_codes::KeyCode,
+    key_mapping::TAP_DANCES,
+    key_scan::{KeyScan, KeyboardReport},
+    NUM_COLS, NUM_ROWS,
+};
+
+/// The maximum number of taps a single tap dance can distinguish.
+pub const MAX_TAPS: usize = 3;
+
+/// The configuration of a tap-dance key, see `TAP_DANCES` in `key_mapping.rs`. It's
+/// performed by the `Action::TapDance` keys with its index.
+#[derive(Copy, Clone)]
+pub struct TapDance {
+    /// The keys sent after one, two or three taps. `KeyCode::Empty` marks an unused tap count.
+    pub taps: [KeyCode; MAX_TAPS],
+
+    /// The key held down while the tap-dance key is held on its first press.
+    /// Falls back to the single-tap key when `KeyCode::Empty`.
+    pub hold: KeyCode,
+
+    /// The key held down while the tap-dance key is held after one or more taps.
+    /// Falls back to the key for the current tap count when `KeyCode::Empty`.
+    pub tap_hold: KeyCode,
+}
+
+#[derive(Copy, Clone, Default)]
+struct TapDanceState {
+    /// The number of presses in the current dance.
+    count: u8,
+
+    /// Whether the tap-dance key was pressed on the previous tick.
+    pressed: bool,
+
+    /// The number of ticks since the tap-dance key was last pressed or released.
+    elapsed_ticks: u16,
+
+    /// The resolved key, held for as long as the tap-dance key stays pressed.
+    held: Option<KeyCode>,
+
+    /// The resolved tapped key, along with the number of ticks it is still reported for.
+    tap: Option<(KeyCode, u16)>,
+}
+
+impl TapDanceState {
+    fn tick(&mut self, dance: &TapDance, pressed: bool, timeout_ticks: u16, tap_ticks: u16) {
+        self.tap = self.tap.filter(|(_, ticks)| *ticks > 1).map(|(key, ticks)| (key, ticks - 1));
+
+        let changed = pressed != self.pressed;
+        self.pressed = pressed;
+
+        if self.held.is_some() {
+            if !pressed {
+                self.held = None;
+                self.count = 0;
+            }
+
+            return;
+        }
+
+        if changed {
+            self.elapsed_ticks = 0;
+            if pressed {
+                self.count = self.count.saturating_add(1);
+            }
+        } else {
+            self.elapsed_ticks = self.elapsed_ticks.saturating_add(1);
+        }
+
+        if self.count == 0 {
+            return;
+        }
+
+        let count = usize::from(self.count).min(MAX_TAPS);
+        let tap_key = dance.taps[count - 1];
+
+        if pressed {
+            if self.elapsed_ticks >= timeout_ticks {
+                let hold_key = if count == 1 { dance.hold } else { dance.tap_hold };
+                self.held = Some(if hold_key == KeyCode::Empty { tap_key } else { hold_key });
+            }
+        } else {
+            // There's no need to wait for the timeout if no further tap could change the outcome.
+            let no_more_taps = dance.taps[count..].iter().all(|key| *key == KeyCode::Empty);
+
+            if self.elapsed_ticks >= timeout_ticks || no_more_taps {
+                self.tap = Some((tap_key, tap_ticks));
+                self.count = 0;
+            }
+        }
+    }
+}
+
+/// `TapDancer` resolves the tap-dance keys defined in `TAP_DANCES`.
+///
+/// # Ticks
+/// Like `Debounce`, `TapDancer` counts in ticks of the scan loop. A dance is resolved once
+/// the key has been released (or held) for `timeout_ticks`, and resolved taps are reported
+/// as pressed for `tap_ticks` so the host sees both the press and the release.
+pub struct TapDancer {
+    states: [TapDanceState; TAP_DANCES.len()],
+    timeout_ticks: u16,
+    tap_ticks: u16,
+}
+
+impl TapDancer {
+    pub fn new(timeout_ticks: u16, tap_ticks: u16) -> Self {
+        Self { states: [TapDanceState::default(); TAP_DANCES.len()], timeout_ticks, tap_ticks }
+    }
+
+    /// Removes the tap-dance keys from `scan` and advances every tap dance by one tick.
+    pub fn tick(&mut self, scan: &mut KeyScan<NUM_ROWS, NUM_COLS>) {
+        let layer_mapping = scan.layer_mapping();
+
+        for (index, (dance, state)) in TAP_DANCES.iter().zip(self.states.iter_mut()).enumerate() {
+            let mut pressed = false;
+
+            for (matrix_column, mapping_column) in scan.iter_mut().zip(layer_mapping) {
+                for (key_pressed, mapping_row) in matrix_column.iter_mut().zip(mapping_column) {
+                    if matches!(mapping_row, Action::TapDance(dance_index) if dance_index == index)
+                    {
+                        pressed |= *key_pressed;
+                        *key_pressed = false;
+                    }
+                }
+            }
+
+            state.tick(dance, pressed, self.timeout_ticks, self.tap_ticks);
+        }
+    }
+
+    /// Adds the keys currently produced by tap dances to `report`.
+    pub fn apply(&self, report: &mut KeyboardReport) {
+        for state in &self.states {
+            if let Some(key) = state.held {
+                report.press(key);
+            }
+
+            if let Some((key, _)) = state.tap {
+                report.press(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_mapping::PROFILES, key_scan::Layer};
+
+    const TIMEOUT_TICKS: u16 = 10;
+    const TAP_TICKS: u16 = 2;
+
+    const DANCE: TapDance = TapDance {
+        taps: [KeyCode::A, KeyCode::B, KeyCode::Empty],
+        hold: KeyCode::LeftShift,
+        tap_hold: KeyCode::Empty,
+    };
+
+    /// Runs a dance through `presses`, a list of (pressed, ticks), returning the key it
+    /// produces on each tick.
+    fn run(presses: &[(bool, u16)]) -> Vec<Option<KeyCode>> {
+        let mut state = TapDanceState::default();
+        let mut keys = Vec::new();
+        for &(pressed, ticks) in presses {
+            for _ in 0..ticks {
+                state.tick(&DANCE, pressed, TIMEOUT_TICKS, TAP_TICKS);
+                keys.push(state.held.or(state.tap.map(|(key, _)| key)));
+            }
+        }
+
+        keys
+    }
+
+    #[test]
+    fn single_tap_resolves_after_the_timeout() {
+        let keys = run(&[(true, 2), (false, TIMEOUT_TICKS + 5)]);
+
+        let resolved_at = 2 + TIMEOUT_TICKS as usize;
+        assert!(keys[..resolved_at].iter().all(Option::is_none));
+        assert_eq!(keys[resolved_at], Some(KeyCode::A));
+    }
+
+    #[test]
+    fn tapped_key_is_reported_for_tap_ticks() {
+        let keys = run(&[(true, 1), (false, TIMEOUT_TICKS + 5)]);
+
+        assert_eq!(keys.iter().filter(|key| key.is_some()).count(), TAP_TICKS as usize);
+    }
+
+    #[test]
+    fn last_tap_count_resolves_without_waiting() {
+        let keys = run(&[(true, 1), (false, 1), (true, 1), (false, 1)]);
+
+        assert_eq!(keys, [None, None, None, Some(KeyCode::B)]);
+    }
+
+    #[test]
+    fn holding_the_first_press_holds_the_hold_key() {
+        let keys = run(&[(true, TIMEOUT_TICKS + 2), (false, 1)]);
+
+        assert_eq!(keys[TIMEOUT_TICKS as usize], Some(KeyCode::LeftShift));
+        assert_eq!(keys.last(), Some(&None));
+    }
+
+    #[test]
+    fn holding_after_a_tap_falls_back_to_the_tap_count_key() {
+        let keys = run(&[(true, 1), (false, 1), (true, TIMEOUT_TICKS + 2)]);
+
+        assert_eq!(keys.last(), Some(&Some(KeyCode::B)));
+    }
+
+    #[test]
+    fn dance_keys_in_the_mapping_perform_their_dance() {
+        let profile = &PROFILES[0];
+        let fn_key = profile
+            .find(Layer::Normal, |action| matches!(action, Action::MomentaryLayer(Layer::Fn)));
+        let dance_key = profile.find(Layer::Fn, |action| matches!(action, Action::TapDance(0)));
+        let mut tap_dancer = TapDancer::new(TIMEOUT_TICKS, TAP_TICKS);
+
+        let mut report = KeyboardReport::default();
+        for pressed in [true, false, true, false] {
+            let positions = if pressed { vec![fn_key, dance_key] } else { vec![fn_key] };
+            let mut scan = KeyScan::with_pressed(profile, &positions);
+            tap_dancer.tick(&mut scan);
+            assert!(!scan[dance_key.0][dance_key.1]);
+
+            report = KeyboardReport::default();
+            tap_dancer.apply(&mut report);
+        }
+
+        let mut expected = KeyboardReport::default();
+        expected.press(TAP_DANCES[0].taps[1]);
+        assert_eq!(report, expected);
+    }
+}
@@ -0,0 +1,313 @@
+//! Text expansion, which replaces an abbreviation with a longer snippet as soon as it has
+//! been typed.
+
+use crate::{
+    host_layout::{legend, SHIFT},
+    key_codes::KeyCode,
+    key_mapping::TEXT_EXPANSIONS,
+    key_scan::KeyboardReport,
+    macros::{Macro, MacroStep},
+};
+
+/// The number of characters remembered, which limits the length of abbreviations and typos.
+pub const MAX_TYPED_LEN: usize = 32;
+
+/// An abbreviation and its expansion, see `TEXT_EXPANSIONS` in `key_mapping.rs`.
+pub struct TextExpansion {
+    /// The characters which trigger the expansion, as printed on the keycaps.
+    pub abbreviation: &'static str,
+
+    /// The macro played after the abbreviation has been erased, usually a `Text` step.
+    pub expansion: Macro,
+}
+
+/// `TypedBuffer` remembers the last `MAX_TYPED_LEN` characters typed, by watching the keys
+/// newly pressed in each report and the text typed by macros. It's shared by text expansion
+/// and autocorrect, so each sees the text the other replaced.
+///
+/// Backspace erases the last character, while keys without a legend (such as arrows) and
+/// shortcuts (anything with Ctrl, Alt or Cmd held) forget everything, as the cursor may
+/// have moved.
+pub struct TypedBuffer {
+    chars: [u8; MAX_TYPED_LEN],
+    len: usize,
+
+    /// The key which typed the last character, if it was newly pressed in the last report.
+    newly_typed: Option<KeyCode>,
+
+    /// The keys in the previous report, used to find newly pressed keys.
+    previous_keycodes: [u8; 6],
+
+    /// A key kept from the host until it's released, see `suppress`.
+    suppressed: Option<KeyCode>,
+}
+
+impl TypedBuffer {
+    pub fn new() -> Self {
+        Self {
+            chars: [0; MAX_TYPED_LEN],
+            len: 0,
+            newly_typed: None,
+            previous_keycodes: [0; 6],
+            suppressed: None,
+        }
+    }
+
+    /// The characters typed, oldest first.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.chars[..self.len]
+    }
+
+    /// Whether older characters may have been dropped to make room for newer ones.
+    pub fn is_full(&self) -> bool {
+        self.len == MAX_TYPED_LEN
+    }
+
+    /// The key which typed the last character, if it was newly pressed in the last report
+    /// and hasn't been suppressed.
+    pub fn newly_typed(&self) -> Option<KeyCode> {
+        self.newly_typed
+    }
+
+    /// Removes the newly typed `key` from `report` and its character from the buffer, and
+    /// keeps it from the host until it's released.
+    pub fn suppress(&mut self, report: &mut KeyboardReport, key: KeyCode) {
+        report.release(key);
+        self.suppressed = Some(key);
+        self.newly_typed = None;
+        self.len = self.len.saturating_sub(1);
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Updates the buffer from the keys in `report`, remembering the key which typed the last
+    /// character if it was newly pressed in this report. A suppressed key is removed from
+    /// `report`.
+    pub fn update(&mut self, report: &mut KeyboardReport) {
+        let shortcut = report.modifier & !SHIFT != 0;
+        let shifted = report.modifier & SHIFT != 0;
+
+        self.newly_typed = None;
+        for keycode in report.keycodes {
+            if keycode == 0 || self.previous_keycodes.contains(&keycode) {
+                continue;
+            }
+
+            let Ok(key) = KeyCode::try_from(keycode) else {
+                continue;
+            };
+
+            self.newly_typed = None;
+            match legend(key, shifted) {
+                _ if shortcut => self.clear(),
+                _ if key == KeyCode::Backspace => self.len = self.len.saturating_sub(1),
+                Some(c) => {
+                    self.push(c as u8);
+                    self.newly_typed = Some(key);
+                },
+                None if key.is_modifier() => {},
+                None => self.clear(),
+            }
+        }
+
+        self.previous_keycodes = report.keycodes;
+
+        if let Some(key) = self.suppressed {
+            if report.keycodes.contains(&(key as u8)) {
+                report.release(key);
+            } else {
+                self.suppressed = None;
+            }
+        }
+    }
+
+    /// Follows a macro which erases `backspaces` characters, then plays `steps` and types
+    /// `suffix`, see `MacroPlayer::play_replacing`. Only the text of `Text` steps is known,
+    /// so any other step which types something forgets everything.
+    pub fn replace(&mut self, backspaces: usize, steps: Macro, suffix: &str) {
+        self.len = self.len.saturating_sub(backspaces);
+
+        for step in steps {
+            match *step {
+                MacroStep::Text(text) => self.push_str(text),
+                MacroStep::Delay(_) | MacroStep::SetUnicodeMethod(_) => {},
+                _ => self.clear(),
+            }
+        }
+
+        self.push_str(suffix);
+    }
+
+    fn push_str(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                ' '..='~' => self.push(c as u8),
+                _ => self.clear(),
+            }
+        }
+    }
+
+    fn push(&mut self, c: u8) {
+        if self.len == MAX_TYPED_LEN {
+            self.chars.copy_within(1.., 0);
+            self.len -= 1;
+        }
+
+        self.chars[self.len] = c;
+        self.len += 1;
+    }
+}
+
+/// `TextExpander` watches what's typed for the abbreviations in `TEXT_EXPANSIONS`.
+///
+/// The key completing an abbreviation is kept from the host while it's held, and the rest
+/// of the abbreviation is erased with Backspace before the expansion is played.
+pub struct TextExpander {
+    /// The number of characters to erase and the expansion to play, until taken by
+    /// `take_action`.
+    action: Option<(usize, Macro)>,
+}
+
+impl TextExpander {
+    pub fn new() -> Self {
+        Self { action: None }
+    }
+
+    /// Watches `typed` for abbreviations, removing the key which completed one from `report`.
+    pub fn apply(&mut self, report: &mut KeyboardReport, typed: &mut TypedBuffer) {
+        let Some(key) = typed.newly_typed() else {
+            return;
+        };
+
+        let Some(expansion) = TEXT_EXPANSIONS
+            .iter()
+            .find(|expansion| typed.as_bytes().ends_with(expansion.abbreviation.as_bytes()))
+        else {
+            return;
+        };
+
+        typed.suppress(report, key);
+        self.action = Some((expansion.abbreviation.len() - 1, expansion.expansion));
+    }
+
+    /// Returns the number of characters to erase and the expansion to play, if an
+    /// abbreviation was just completed.
+    pub fn take_action(&mut self) -> Option<(usize, Macro)> {
+        self.action.take()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::action::{MOD_CTRL, MOD_SHIFT};
+
+    /// Presses and releases each of `keys` with `modifier` held, updating `typed` with every
+    /// report and then passing both to `apply`. Returns the report which pressed the last key.
+    pub fn type_keys(
+        typed: &mut TypedBuffer,
+        mut apply: impl FnMut(&mut KeyboardReport, &mut TypedBuffer),
+        modifier: u8,
+        keys: &[KeyCode],
+    ) -> KeyboardReport {
+        let mut last = KeyboardReport::default();
+        for key in keys {
+            let mut report = KeyboardReport { modifier, ..KeyboardReport::default() };
+            report.press(*key);
+            typed.update(&mut report);
+            apply(&mut report, typed);
+            last = report;
+
+            let mut report = KeyboardReport::default();
+            typed.update(&mut report);
+            apply(&mut report, typed);
+        }
+
+        last
+    }
+
+    fn type_plain(typed: &mut TypedBuffer, keys: &[KeyCode]) {
+        type_keys(typed, |_, _| {}, 0, keys);
+    }
+
+    #[test]
+    fn backspace_erases_and_shortcuts_forget() {
+        let mut typed = TypedBuffer::new();
+        type_plain(&mut typed, &[KeyCode::A, KeyCode::B, KeyCode::Backspace]);
+        assert_eq!(typed.as_bytes(), b"a");
+
+        type_keys(&mut typed, |_, _| {}, MOD_SHIFT, &[KeyCode::Num1]);
+        assert_eq!(typed.as_bytes(), b"a!");
+
+        type_keys(&mut typed, |_, _| {}, MOD_CTRL, &[KeyCode::C]);
+        assert_eq!(typed.as_bytes(), b"");
+    }
+
+    #[test]
+    fn oldest_characters_are_dropped_when_full() {
+        let mut typed = TypedBuffer::new();
+        type_plain(&mut typed, &[KeyCode::A; MAX_TYPED_LEN]);
+        assert!(typed.is_full());
+
+        type_plain(&mut typed, &[KeyCode::B]);
+        assert_eq!(typed.as_bytes().len(), MAX_TYPED_LEN);
+        assert_eq!(typed.as_bytes().last(), Some(&b'b'));
+    }
+
+    #[test]
+    fn abbreviation_is_expanded() {
+        let mut typed = TypedBuffer::new();
+        let mut expander = TextExpander::new();
+        let keys = [KeyCode::Semicolon, KeyCode::S, KeyCode::I, KeyCode::G];
+        let report = type_keys(&mut typed, |r, t| expander.apply(r, t), 0, &keys);
+
+        assert_eq!(report, KeyboardReport::default());
+        let (backspaces, expansion) = expander.take_action().unwrap();
+        assert_eq!(backspaces, 3);
+        assert_eq!(typed.as_bytes(), b";si");
+
+        let sig = TEXT_EXPANSIONS.iter().find(|entry| entry.abbreviation == ";sig").unwrap();
+        assert_eq!(expansion, sig.expansion);
+    }
+
+    #[test]
+    fn replaced_text_is_followed() {
+        let mut typed = TypedBuffer::new();
+        typed.push_str("ok ;si");
+
+        typed.replace(3, &[MacroStep::Text("Hi")], "!");
+        assert_eq!(typed.as_bytes(), b"ok Hi!");
+    }
+
+    #[test]
+    fn suppressed_key_is_kept_from_the_host_until_released() {
+        let mut typed = TypedBuffer::new();
+        let mut report = KeyboardReport::default();
+        report.press(KeyCode::X);
+        typed.update(&mut report);
+        typed.suppress(&mut report, KeyCode::X);
+        assert_eq!(typed.newly_typed(), None);
+
+        let mut held = KeyboardReport::default();
+        held.press(KeyCode::X);
+        held.press(KeyCode::Y);
+        typed.update(&mut held);
+
+        assert_eq!(held.keycodes.iter().filter(|k| **k != 0).count(), 1);
+        assert!(held.keycodes.contains(&(KeyCode::Y as u8)));
+    }
+
+    #[test]
+    fn replace_follows_only_known_text() {
+        let mut typed = TypedBuffer::new();
+        type_plain(&mut typed, &[KeyCode::A, KeyCode::B, KeyCode::C]);
+
+        typed.replace(2, &[MacroStep::Text("xy"), MacroStep::Delay(10)], " ");
+        assert_eq!(typed.as_bytes(), b"axy ");
+
+        typed.replace(0, &[MacroStep::Unicode("—")], "z");
+        assert_eq!(typed.as_bytes(), b"z");
+    }
+}
@@ -0,0 +1,87 @@
+//! Typing strings on the host, one report at a time.
+
+use crate::{host_layout::HostLayout, key_scan::KeyboardReport, unicode::UnicodeMethod};
+
+/// Every ASCII character, so any of them can be typed as a `&'static str`.
+static ASCII: [u8; 128] = {
+    let mut ascii = [0; 128];
+    let mut c = 0;
+    while c < ascii.len() {
+        ascii[c] = c as u8;
+        c += 1;
+    }
+
+    ascii
+};
+
+/// Returns `c` as a string, or an empty one if it isn't ASCII.
+pub fn ascii_str(c: u8) -> &'static str {
+    let c = c as usize;
+    ASCII.get(c..=c).and_then(|c| core::str::from_utf8(c).ok()).unwrap_or_default()
+}
+
+/// `Typist` types a string as a sequence of reports, using the keys of the host's keyboard
+/// layout (with Shift or AltGr where needed) for the characters it has, and the host's
+/// Unicode input method for everything else.
+///
+/// Every report has to reach the host before the next one is taken, otherwise a press and
+/// its release could be merged away by the `report != last_report` check in `main`.
+#[derive(Copy, Clone)]
+pub struct Typist {
+    text: &'static str,
+
+    /// The index of the character being typed.
+    text_index: usize,
+
+    /// The index of the next report in typing the current character.
+    report_index: usize,
+
+    /// Whether every character is typed with the Unicode input method.
+    unicode_only: bool,
+}
+
+impl Typist {
+    pub const fn new(text: &'static str) -> Self {
+        Self { text, text_index: 0, report_index: 0, unicode_only: false }
+    }
+
+    /// Types every character of `text` with the Unicode input method, even if the host's
+    /// keyboard layout has a key for it.
+    pub const fn unicode(text: &'static str) -> Self {
+        Self { unicode_only: true, ..Self::new(text) }
+    }
+
+    /// Returns the next report to send, or `None` once the whole string has been typed.
+    /// Characters which can't be typed at all are skipped.
+    pub fn next_report(
+        &mut self,
+        layout: HostLayout,
+        unicode_method: UnicodeMethod,
+    ) -> Option<KeyboardReport> {
+        loop {
+            let c = self.text[self.text_index..].chars().next()?;
+
+            let key = if self.unicode_only { None } else { layout.key_for(c) };
+            let report = match key {
+                Some((modifier, key)) => match self.report_index {
+                    0 => {
+                        let mut report = KeyboardReport { modifier, ..KeyboardReport::default() };
+                        report.press(key);
+                        Some(report)
+                    },
+                    1 => Some(KeyboardReport::default()),
+                    _ => None,
+                },
+                None => unicode_method.report(c, self.report_index),
+            };
+
+            if report.is_some() {
+                self.report_index += 1;
+                return report;
+            }
+
+            self.text_index += c.len_utf8();
+            self.report_index = 0;
+        }
+    }
+}
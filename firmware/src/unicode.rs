@@ -0,0 +1,218 @@
+//! Typing arbitrary Unicode characters, using one of the input methods of the host OS.
+
+use crate::{
+    action::{MOD_ALT, MOD_CTRL, MOD_SHIFT},
+    key_codes::KeyCode::{self, *},
+    key_scan::KeyboardReport,
+};
+use defmt::Format;
+
+const HEX_DIGITS: [KeyCode; 16] =
+    [Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9, A, B, C, D, E, F];
+
+/// The hex digits with 0-9 on the numeric keypad, for Windows Alt codes.
+const KEYPAD_HEX_DIGITS: [KeyCode; 16] = [
+    Keypad0, Keypad1, Keypad2, Keypad3, Keypad4, Keypad5, Keypad6, Keypad7, Keypad8, Keypad9, A, B,
+    C, D, E, F,
+];
+
+/// How Unicode characters are entered on the host, which has to be set up to match.
+#[allow(unused)]
+#[derive(Copy, Clone, Debug, Format, PartialEq)]
+pub enum UnicodeMethod {
+    /// macOS "Unicode Hex Input" source: the UTF-16 code units typed in hex while holding
+    /// Option.
+    MacOs,
+
+    /// Linux with IBus (or GTK): Ctrl+Shift+U, the code point in hex, then Space.
+    Linux,
+
+    /// Windows with WinCompose, using Right Alt as the compose key: compose, U, the code
+    /// point in hex, then Enter.
+    WinCompose,
+
+    /// Windows hex Alt codes: the code point in hex after Keypad +, while holding Alt, with
+    /// the digits 0-9 on the numeric keypad. Needs `EnableHexNumpad` set in the registry.
+    ///
+    /// Only characters in the Basic Multilingual Plane (up to U+FFFF) can be typed, others
+    /// are skipped.
+    WindowsAltCodes,
+}
+
+impl UnicodeMethod {
+    /// The modifiers held while the character is typed.
+    fn held_modifiers(self) -> u8 {
+        match self {
+            UnicodeMethod::MacOs | UnicodeMethod::WindowsAltCodes => MOD_ALT,
+            UnicodeMethod::Linux | UnicodeMethod::WinCompose => 0,
+        }
+    }
+
+    /// Returns the `index`-th key (with its modifiers) tapped to type `c`, or `None` after the
+    /// last one.
+    fn chord(self, c: char, index: usize) -> Option<(u8, KeyCode)> {
+        let code = c as u32;
+        let mut utf16 = [0; 2];
+        let units = c.encode_utf16(&mut utf16);
+
+        // Hex digits are typed without leading zeros, but with at least four of them.
+        let digits = (8 - code.leading_zeros() as usize / 4).max(4);
+        let nibble = |value: u32, digits: usize, index: usize| {
+            (index < digits).then(|| (value >> (4 * (digits - 1 - index))) as usize & 0xF)
+        };
+        let hex = |value, digits, index| nibble(value, digits, index).map(|n| (0, HEX_DIGITS[n]));
+
+        match self {
+            UnicodeMethod::MacOs => {
+                let unit = units.get(index / 4)?;
+                hex(*unit as u32, 4, index % 4)
+            },
+            UnicodeMethod::Linux => match index {
+                0 => Some((MOD_CTRL | MOD_SHIFT, U)),
+                i if i <= digits => hex(code, digits, i - 1),
+                i if i == digits + 1 => Some((0, Space)),
+                _ => None,
+            },
+            UnicodeMethod::WinCompose => match index {
+                0 => Some((0, RightAlt)),
+                1 => Some((0, U)),
+                i if i <= digits + 1 => hex(code, digits, i - 2),
+                i if i == digits + 2 => Some((0, Enter)),
+                _ => None,
+            },
+            UnicodeMethod::WindowsAltCodes => match index {
+                _ if code > 0xFFFF => None,
+                0 => Some((0, KeypadPlus)),
+                i => nibble(code, 4, i - 1).map(|n| (0, KEYPAD_HEX_DIGITS[n])),
+            },
+        }
+    }
+
+    /// Returns the keys held in the `index`-th report of typing `c`, or `None` once `c` has
+    /// been typed (or if it can't be typed with this method).
+    ///
+    /// Every key is pressed and released in reports of their own. The method's modifiers
+    /// are held in a report before the first key, and released in a report after the last.
+    pub fn report(self, c: char, index: usize) -> Option<KeyboardReport> {
+        let chords = (0..).take_while(|i| self.chord(c, *i).is_some()).count();
+        if chords == 0 {
+            return None;
+        }
+
+        let held = self.held_modifiers();
+        let mut report = KeyboardReport { modifier: held, ..KeyboardReport::default() };
+
+        let index = match (held, index) {
+            (0, index) => index,
+            (_, 0) => return Some(report),
+            (_, index) => index - 1,
+        };
+
+        if index < 2 * chords {
+            if index % 2 == 0 {
+                let (modifiers, key) = self.chord(c, index / 2)?;
+                report.modifier |= modifiers;
+                report.press(key);
+            }
+
+            Some(report)
+        } else if held != 0 && index == 2 * chords {
+            Some(KeyboardReport::default())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns every report of typing `c` with `method`.
+    fn reports(method: UnicodeMethod, c: char) -> Vec<KeyboardReport> {
+        (0..).map_while(|index| method.report(c, index)).collect()
+    }
+
+    /// Returns the keys tapped to type `c` with `method`, with their modifiers. A modifier
+    /// tapped on its own is returned with `Empty`.
+    fn taps(method: UnicodeMethod, c: char) -> Vec<(u8, KeyCode)> {
+        let held = KeyboardReport { modifier: method.held_modifiers(), ..Default::default() };
+        reports(method, c)
+            .into_iter()
+            .filter(|report| *report != held && *report != KeyboardReport::default())
+            .map(|report| (report.modifier, KeyCode::try_from(report.keycodes[0]).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn every_key_is_released_before_the_next() {
+        for method in [
+            UnicodeMethod::MacOs,
+            UnicodeMethod::Linux,
+            UnicodeMethod::WinCompose,
+            UnicodeMethod::WindowsAltCodes,
+        ] {
+            let reports = reports(method, 'é');
+            let held = KeyboardReport { modifier: method.held_modifiers(), ..Default::default() };
+
+            // With held modifiers, the first report only holds them, so each key's release
+            // comes one report later.
+            let first_release = if held.modifier == 0 { 1 } else { 2 };
+            for (index, report) in reports.iter().enumerate().skip(first_release) {
+                if index % 2 == first_release % 2 {
+                    assert_eq!(*report, held, "{method:?}");
+                }
+            }
+
+            assert_eq!(reports.last(), Some(&KeyboardReport::default()), "{method:?}");
+        }
+    }
+
+    #[test]
+    fn macos_types_utf16_code_units_holding_option() {
+        let alt = |key| (MOD_ALT, key);
+        assert_eq!(taps(UnicodeMethod::MacOs, 'é'), [alt(Num0), alt(Num0), alt(E), alt(Num9)]);
+        assert_eq!(
+            taps(UnicodeMethod::MacOs, '😀').into_iter().map(|(_, key)| key).collect::<Vec<_>>(),
+            [D, Num8, Num3, D, D, E, Num0, Num0]
+        );
+    }
+
+    #[test]
+    fn linux_types_the_code_point_after_ctrl_shift_u() {
+        assert_eq!(
+            taps(UnicodeMethod::Linux, 'é'),
+            [(MOD_CTRL | MOD_SHIFT, U), (0, Num0), (0, Num0), (0, E), (0, Num9), (0, Space)]
+        );
+        assert_eq!(
+            taps(UnicodeMethod::Linux, '😀'),
+            [
+                (MOD_CTRL | MOD_SHIFT, U),
+                (0, Num1),
+                (0, F),
+                (0, Num6),
+                (0, Num0),
+                (0, Num0),
+                (0, Space)
+            ]
+        );
+    }
+
+    #[test]
+    fn wincompose_types_the_code_point_after_compose_u() {
+        assert_eq!(
+            taps(UnicodeMethod::WinCompose, 'é'),
+            [(MOD_ALT << 4, Empty), (0, U), (0, Num0), (0, Num0), (0, E), (0, Num9), (0, Enter)]
+        );
+    }
+
+    #[test]
+    fn windows_alt_codes_type_digits_on_the_keypad() {
+        let alt = |key| (MOD_ALT, key);
+        assert_eq!(
+            taps(UnicodeMethod::WindowsAltCodes, 'é'),
+            [alt(KeypadPlus), alt(Keypad0), alt(Keypad0), alt(E), alt(Keypad9)]
+        );
+        assert!(reports(UnicodeMethod::WindowsAltCodes, '😀').is_empty());
+    }
+}